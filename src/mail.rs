@@ -0,0 +1,840 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+
+/// A patch produced by `git format-patch`, split into its headers and body
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Patch {
+    pub fn parse(content: &str) -> Result<Self> {
+        let content = match content.strip_prefix("From ") {
+            Some(rest) => rest.split_once('\n').map(|(_, r)| r).unwrap_or_default(),
+            None => content,
+        };
+
+        // Empty commits are formatted without a body
+        let (head, body) = content
+            .split_once("\n\n")
+            .unwrap_or((content.trim_end(), ""));
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in head.lines() {
+            if line.starts_with([' ', '\t']) {
+                let (_, value) = headers
+                    .last_mut()
+                    .ok_or(miette!("Patch starts with a header continuation"))?;
+                value.push('\n');
+                value.push_str(line);
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(miette!("Invalid patch header: {line}"))?;
+            headers.push((name.to_string(), value.trim_start().to_string()));
+        }
+
+        Ok(Self {
+            headers,
+            body: body.to_string(),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read patch {path:?}"))?;
+
+        Self::parse(&content).wrap_err_with(|| format!("Could not parse patch {path:?}"))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Review trailers (Reviewed-by, Acked-by, Tested-by) of the patch, as (trailer, address)
+    pub fn review_trailers(&self) -> Vec<(String, String)> {
+        review_trailers(&self.body)
+    }
+
+    pub fn addresses(&self, name: &str) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, v)| split_addresses(v))
+            .collect()
+    }
+
+    /// Subject without the `[PATCH ...]` prefix, unfolded
+    pub fn subject(&self) -> String {
+        let subject = self.header("Subject").unwrap_or_default().replace('\n', "");
+        match subject.strip_prefix('[').and_then(|s| s.split_once("] ")) {
+            Some((_, subject)) => subject.to_string(),
+            None => subject,
+        }
+    }
+
+    /// Body without the signature added by `format-patch`
+    fn content(&self) -> &str {
+        match self.body.rfind("\n-- \n") {
+            Some(idx) => &self.body[..idx + 1],
+            None => &self.body,
+        }
+    }
+
+    /// Start of the line starting with `prefix` in the content
+    fn find_line(&self, prefix: &str) -> Option<usize> {
+        let content = self.content();
+        match content.starts_with(prefix) {
+            true => Some(0),
+            false => content.find(&format!("\n{prefix}")).map(|idx| idx + 1),
+        }
+    }
+
+    /// Commit message, or cover letter text
+    pub fn message(&self) -> &str {
+        let content = self.content();
+        // Cover letters end with the interdiff or range-diff when there is one
+        let end = [
+            "---\n",
+            "Interdiff against ",
+            "Range-diff against ",
+            "diff --git ",
+        ]
+        .iter()
+        .find_map(|prefix| self.find_line(prefix))
+        .unwrap_or(content.len());
+
+        &content[..end]
+    }
+
+    /// Diffstat of the patch, as generated by `format-patch`
+    pub fn diffstat(&self) -> &str {
+        let content = self.content();
+        let Some(start) = self.find_line("---\n").map(|idx| idx + "---\n".len()) else {
+            return "";
+        };
+        let end = self.find_line("diff --git ").unwrap_or(content.len());
+
+        content[start..end.max(start)].trim_end()
+    }
+
+    pub fn diff(&self) -> &str {
+        let content = self.content();
+        self.find_line("diff --git ")
+            .map(|idx| &content[idx..])
+            .unwrap_or_default()
+    }
+}
+
+/// A patch ready to be sent, with its threading and recipients resolved
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub file_name: String,
+    pub message_id: String,
+    pub in_reply_to: Option<String>,
+    pub references: Vec<String>,
+    pub subject: String,
+    pub author: String,
+    pub sender: String,
+    pub date: String,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    /// Why each recipient was added, as (address, reason)
+    pub reasons: Vec<(String, String)>,
+    /// Files modified by the patch
+    pub files: Vec<String>,
+    extra_headers: Vec<(String, String)>,
+    body: String,
+}
+
+/// Headers that are generated when preparing an [Email]
+const MANAGED_HEADERS: &[&str] = &[
+    "From",
+    "To",
+    "Cc",
+    "Date",
+    "Subject",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+];
+
+impl Email {
+    /// Thread the email as a reply to `message_id`
+    pub fn reply_to(&mut self, message_id: &str) {
+        self.in_reply_to = Some(message_id.to_string());
+        self.references = vec![message_id.to_string()];
+    }
+
+    pub fn review_trailers(&self) -> Vec<(String, String)> {
+        review_trailers(&self.body)
+    }
+
+    pub fn is_cover_letter(&self) -> bool {
+        self.file_name.ends_with("cover-letter.patch")
+    }
+
+    /// Add a recipient, unless it is already present
+    pub fn add_recipient(&mut self, recipient: &Recipient) {
+        let address = address_of(&recipient.address).to_lowercase();
+        let present = self
+            .to
+            .iter()
+            .chain(&self.cc)
+            .any(|a| address_of(a).to_lowercase() == address);
+
+        if !present {
+            match recipient.field {
+                Field::To => self.to.push(recipient.address.clone()),
+                Field::Cc => self.cc.push(recipient.address.clone()),
+            }
+        }
+
+        self.reasons.push((address, recipient.reason.clone()));
+    }
+
+    /// The reasons for which `address` is a recipient
+    pub fn reasons_for<'a>(&'a self, address: &str) -> impl Iterator<Item = &'a str> {
+        let address = address_of(address).to_lowercase();
+        self.reasons
+            .iter()
+            .filter(move |(a, _)| *a == address)
+            .map(|(_, r)| r.as_str())
+    }
+
+    /// All the addresses the email must be delivered to
+    pub fn recipients(&self) -> impl Iterator<Item = &str> {
+        self.to.iter().chain(&self.cc).map(|a| address_of(a))
+    }
+
+    fn render_with(&self, from: &str, body_from: Option<&str>) -> String {
+        let mut out = format!("From: {from}\n");
+        if !self.to.is_empty() {
+            out += &format!("To: {}\n", self.to.join(",\n\t"));
+        }
+        if !self.cc.is_empty() {
+            out += &format!("Cc: {}\n", self.cc.join(",\n\t"));
+        }
+        out += &format!("Subject: {}\n", self.subject);
+        out += &format!("Date: {}\n", self.date);
+        out += &format!("Message-ID: {}\n", self.message_id);
+        if let Some(in_reply_to) = &self.in_reply_to {
+            out += &format!("In-Reply-To: {in_reply_to}\n");
+        }
+        if !self.references.is_empty() {
+            out += &format!("References: {}\n", self.references.join("\n\t"));
+        }
+        for (name, value) in &self.extra_headers {
+            out += &format!("{name}: {value}\n");
+        }
+        out.push('\n');
+        if let Some(author) = body_from {
+            out += &format!("From: {author}\n\n");
+        }
+        out += &self.body;
+
+        out
+    }
+
+    /// Render the email as sent on the wire by `sender`
+    ///
+    /// Like `git send-email`, the author is kept in the body when it is not the sender.
+    pub fn render(&self) -> String {
+        let body_from =
+            (address_of(&self.author) != address_of(&self.sender)).then_some(self.author.as_str());
+        self.render_with(&self.sender, body_from)
+    }
+
+    /// Render the email as a patch authored by its author, to be handed to `git send-email`
+    pub fn render_patch(&self) -> String {
+        self.render_with(&self.author, None)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    To,
+    Cc,
+}
+
+/// An address to send an email to, and why it was chosen
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub address: String,
+    pub field: Field,
+    pub reason: String,
+}
+
+impl Recipient {
+    pub fn new(address: impl Into<String>, field: Field, reason: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            field,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Aliases standing for lists of addresses
+///
+/// Aliases are case insensitive, and may refer to other aliases.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(transparent)]
+pub struct AddressBook(BTreeMap<String, Vec<String>>);
+
+impl AddressBook {
+    /// Replace a recipient that is an alias by the addresses it stands for
+    pub fn expand(&self, recipient: Recipient) -> Result<Vec<Recipient>> {
+        self.expand_with(recipient, &mut Vec::new())
+    }
+
+    fn expand_with(&self, recipient: Recipient, stack: &mut Vec<String>) -> Result<Vec<Recipient>> {
+        let name = recipient.address.trim().to_lowercase();
+        let Some(addresses) = self.0.get(&name) else {
+            return Ok(vec![recipient]);
+        };
+
+        if stack.contains(&name) {
+            return Err(miette!("Alias `{name}` refers to itself"));
+        }
+
+        stack.push(name);
+        let mut expanded = Vec::new();
+        for address in addresses {
+            expanded.extend(self.expand_with(
+                Recipient::new(
+                    address,
+                    recipient.field,
+                    format!(
+                        "{} (alias `{}`)",
+                        recipient.reason,
+                        recipient.address.trim()
+                    ),
+                ),
+                stack,
+            )?);
+        }
+        stack.pop();
+
+        Ok(expanded)
+    }
+}
+
+/// Check that an address is either `address@domain` or `Name <address@domain>`
+pub fn validate_address(mailbox: &str) -> Result<()> {
+    let address = match mailbox.trim().strip_suffix('>') {
+        None => mailbox.trim(),
+        Some(mailbox) => {
+            let (name, address) = mailbox
+                .rsplit_once('<')
+                .ok_or(miette!("`{mailbox}>` has an unmatched `>`"))?;
+            if name.matches('"').count() % 2 != 0 {
+                return Err(miette!("`{mailbox}>` has an unterminated quote"));
+            }
+            address
+        }
+    };
+
+    address
+        .parse::<lettre::Address>()
+        .map(|_| ())
+        .map_err(|e| match address.contains('@') {
+            true => miette!("`{mailbox}` is not a valid address: {e}"),
+            false => miette!("`{mailbox}` is neither an address nor a known alias"),
+        })
+}
+
+/// Lists the patches of a version directory, the cover letter being first
+///
+/// `format-patch` writes empty commits as empty files, which are skipped.
+pub fn version_patches(version_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut patches = version_dir
+        .read_dir()
+        .into_diagnostic()
+        .wrap_err("Could not read patch directory")?
+        .map(|e| -> Result<_> {
+            let e = e
+                .into_diagnostic()
+                .wrap_err("Could not read patch directory entry")?;
+            let empty = e.metadata().map(|m| m.len() == 0).unwrap_or(false);
+            Ok((e.path(), empty))
+        })
+        .filter(|p| {
+            p.as_ref()
+                .map(|(p, empty)| !empty && p.extension().is_some_and(|e| e == "patch"))
+                .unwrap_or(true)
+        })
+        .map(|p| p.map(|(p, _)| p))
+        .collect::<Result<Vec<_>>>()?;
+
+    patches.sort();
+
+    Ok(patches)
+}
+
+/// Prepare all the patches of a series for sending
+///
+/// Every patch is threaded as a reply to the first one (the cover letter), as done by `git
+/// send-email` by default.
+pub fn prepare_series(
+    patches: &[PathBuf],
+    sender: &str,
+    recipients: &[Recipient],
+) -> Result<Vec<Email>> {
+    let now = chrono::Local::now();
+    let domain = address_of(sender)
+        .split_once('@')
+        .map(|(_, d)| d)
+        .unwrap_or("localhost")
+        .to_string();
+
+    let mut root: Option<String> = None;
+    patches
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let patch = Patch::read(path)?;
+
+            let author = patch
+                .header("From")
+                .ok_or(miette!("Patch {path:?} has no author"))?
+                .to_string();
+            let subject = patch
+                .header("Subject")
+                .ok_or(miette!("Patch {path:?} has no subject"))?
+                .to_string();
+
+            let recipients = recipients
+                .iter()
+                .cloned()
+                .chain(
+                    patch
+                        .addresses("To")
+                        .into_iter()
+                        .map(|a| Recipient::new(a, Field::To, "listed in the patch")),
+                )
+                .chain(
+                    patch
+                        .addresses("Cc")
+                        .into_iter()
+                        .map(|a| Recipient::new(a, Field::Cc, "listed in the patch")),
+                )
+                .collect::<Vec<_>>();
+
+            let message_id = format!(
+                "<{}.{}-{}-gsm@{domain}>",
+                now.timestamp(),
+                now.timestamp_subsec_nanos(),
+                i + 1
+            );
+            let date = (now + chrono::Duration::seconds(i as i64)).to_rfc2822();
+
+            let in_reply_to = root.clone();
+            if root.is_none() {
+                root = Some(message_id.clone());
+            }
+
+            let mut email = Email {
+                file_name: path
+                    .file_name()
+                    .ok_or(miette!("Patch {path:?} has no file name"))?
+                    .to_string_lossy()
+                    .into_owned(),
+                message_id,
+                references: in_reply_to.iter().cloned().collect(),
+                in_reply_to,
+                subject,
+                author,
+                sender: sender.to_string(),
+                date,
+                to: Vec::new(),
+                cc: Vec::new(),
+                reasons: Vec::new(),
+                files: modified_files(&patch.body),
+                extra_headers: patch
+                    .headers
+                    .into_iter()
+                    .filter(|(n, _)| !MANAGED_HEADERS.iter().any(|m| m.eq_ignore_ascii_case(n)))
+                    .collect(),
+                body: patch.body,
+            };
+
+            for recipient in &recipients {
+                email.add_recipient(recipient);
+            }
+
+            Ok(email)
+        })
+        .collect()
+}
+
+/// Compute the sender of the emails, like `git send-email` would
+pub fn sender(from: Option<&str>, git: impl Fn(&[&str]) -> Result<String>) -> Result<String> {
+    if let Some(from) = from {
+        return Ok(from.to_string());
+    }
+
+    if let Ok(from) = git(&["config", "sendemail.from"]) {
+        return Ok(from);
+    }
+
+    let ident = git(&["var", "GIT_COMMITTER_IDENT"]).wrap_err("Could not find sender identity")?;
+    Ok(match ident.find('>') {
+        Some(end) => ident[..=end].to_string(),
+        None => ident,
+    })
+}
+
+/// Extract the bare address from `Name <address>`
+pub fn address_of(mailbox: &str) -> &str {
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+/// Name part of a mailbox, or the address when it has no name
+pub fn display_name(mailbox: &str) -> &str {
+    match mailbox.rfind('<') {
+        Some(start) if !mailbox[..start].trim().is_empty() => {
+            mailbox[..start].trim().trim_matches('"')
+        }
+        _ => address_of(mailbox),
+    }
+}
+
+/// Split an address list header, honoring quoted names
+pub fn split_addresses(list: &str) -> Vec<String> {
+    let mut addresses = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut angle = false;
+
+    for c in list.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            ',' if !quoted && !angle => {
+                addresses.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    addresses.push(current);
+
+    addresses
+        .into_iter()
+        .map(|a| a.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+/// Trailers denoting a review of the patch
+const REVIEW_TRAILERS: &[&str] = &["Reviewed-by", "Acked-by", "Tested-by"];
+
+fn review_trailers(body: &str) -> Vec<(String, String)> {
+    body.lines()
+        .take_while(|l| *l != "---")
+        .filter_map(|l| l.split_once(':'))
+        .filter(|(trailer, _)| {
+            REVIEW_TRAILERS
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(trailer))
+        })
+        .map(|(trailer, value)| (trailer.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Split an mbox file in its messages
+pub fn split_mbox(content: &str) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current: Option<String> = None;
+    let mut previous_blank = true;
+
+    for line in content.lines() {
+        if previous_blank && line.starts_with("From ") {
+            messages.extend(current.take());
+            current = Some(String::new());
+        } else if let Some(message) = &mut current {
+            // mboxrd unquoting, a `From ` line not following a blank line is kept as is
+            let line = match line.strip_prefix('>') {
+                Some(unquoted) if unquoted.trim_start_matches('>').starts_with("From ") => unquoted,
+                _ => line,
+            };
+            *message += line;
+            message.push('\n');
+        }
+
+        previous_blank = line.is_empty();
+    }
+    messages.extend(current);
+
+    messages
+}
+
+/// Files modified by a patch, from its `diff --git` lines
+fn modified_files(body: &str) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+
+    for line in body.lines() {
+        let Some(paths) = line.strip_prefix("diff --git a/") else {
+            continue;
+        };
+
+        let Some((old, new)) = paths.split_once(" b/") else {
+            continue;
+        };
+
+        for file in [old, new] {
+            if !files.iter().any(|f| f == file) {
+                files.push(file.to_string());
+            }
+        }
+    }
+
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COVER_LETTER: &str =
+        "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: Sender <sender@example.com>
+Date: Mon, 1 Jan 2024 00:00:00 +0000
+Subject: [PATCH 0/1] Title
+
+Cover letter
+";
+
+    const PATCH: &str = "From 1111111111111111111111111111111111111111 Mon Sep 17 00:00:00 2001
+From: Author <author@example.com>
+Date: Mon, 1 Jan 2024 00:00:00 +0000
+Subject: [PATCH 1/1] Change
+Cc: Reviewer <reviewer@example.com>
+X-Custom: kept
+
+Message
+
+Reviewed-by: Reviewer <reviewer@example.com>
+---
+diff --git a/src/lib.rs b/src/lib.rs
+";
+
+    fn prepare(recipients: &[Recipient]) -> Vec<Email> {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let patches = [
+            ("v1-0000-cover-letter.patch", COVER_LETTER),
+            ("v1-0001-change.patch", PATCH),
+        ]
+        .map(|(name, content)| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        });
+
+        prepare_series(&patches, "Sender <sender@example.com>", recipients).unwrap()
+    }
+
+    #[test]
+    fn prepare_series_threads_on_cover_letter() {
+        let emails = prepare(&[]);
+
+        let [cover_letter, patch] = emails.as_slice() else {
+            panic!("expected two emails, got {}", emails.len());
+        };
+        assert!(cover_letter.is_cover_letter());
+        assert_eq!(cover_letter.in_reply_to, None);
+        assert!(cover_letter.references.is_empty());
+        assert!(cover_letter.message_id.ends_with("-1-gsm@example.com>"));
+
+        assert_eq!(patch.in_reply_to.as_ref(), Some(&cover_letter.message_id));
+        assert_eq!(patch.references, vec![cover_letter.message_id.clone()]);
+        assert_ne!(patch.message_id, cover_letter.message_id);
+        assert_eq!(patch.files, vec!["src/lib.rs"]);
+    }
+
+    #[test]
+    fn prepare_series_merges_recipients() {
+        let emails = prepare(&[
+            Recipient::new("list@example.com", Field::To, "configured"),
+            Recipient::new("reviewer@example.com", Field::To, "configured"),
+        ]);
+
+        assert_eq!(
+            emails[0].to,
+            vec!["list@example.com", "reviewer@example.com"]
+        );
+        assert!(emails[0].cc.is_empty());
+
+        // The patch Cc is already a recipient, it is only given another reason
+        assert_eq!(
+            emails[1].to,
+            vec!["list@example.com", "reviewer@example.com"]
+        );
+        assert!(emails[1].cc.is_empty());
+        assert_eq!(
+            emails[1]
+                .reasons_for("Reviewer <reviewer@example.com>")
+                .collect::<Vec<_>>(),
+            vec!["configured", "listed in the patch"]
+        );
+    }
+
+    #[test]
+    fn render_keeps_author_in_body() {
+        let emails = prepare(&[Recipient::new("list@example.com", Field::To, "configured")]);
+        let patch = &emails[1];
+
+        let rendered = patch.render();
+        let (head, body) = rendered.split_once("\n\n").unwrap();
+        assert!(head.starts_with("From: Sender <sender@example.com>\n"));
+        assert!(head.contains("\nTo: list@example.com\n"));
+        assert!(head.contains("\nCc: Reviewer <reviewer@example.com>\n"));
+        assert!(head.contains(&format!("\nMessage-ID: {}\n", patch.message_id)));
+        assert!(head.contains(&format!("\nIn-Reply-To: {}\n", emails[0].message_id)));
+        assert!(head.contains("\nX-Custom: kept"));
+        assert!(body.starts_with("From: Author <author@example.com>\n\nMessage\n"));
+
+        let rendered = patch.render_patch();
+        let (head, body) = rendered.split_once("\n\n").unwrap();
+        assert!(head.starts_with("From: Author <author@example.com>\n"));
+        assert!(body.starts_with("Message\n"));
+    }
+
+    #[test]
+    fn split_mbox_unquotes_from_lines() {
+        let mbox = "From a@example.com Mon Jan  1 00:00:00 2024
+Subject: first
+
+>From the start
+>>From quoted twice
+From the middle of a paragraph
+> quoted reply
+
+From b@example.com Mon Jan  1 00:00:00 2024
+Subject: second
+
+body
+";
+
+        assert_eq!(
+            split_mbox(mbox),
+            vec![
+                "Subject: first\n\nFrom the start\n>From quoted twice\n\
+                 From the middle of a paragraph\n> quoted reply\n\n",
+                "Subject: second\n\nbody\n",
+            ]
+        );
+    }
+
+    fn address_book(aliases: &[(&str, &[&str])]) -> AddressBook {
+        AddressBook(
+            aliases
+                .iter()
+                .map(|(alias, addresses)| {
+                    (
+                        alias.to_string(),
+                        addresses.iter().map(|a| a.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn expand_nested_aliases() {
+        let book = address_book(&[
+            ("net", &["netdev@example.com", "maint"]),
+            ("maint", &["Main Tainer <maint@example.com>"]),
+        ]);
+
+        let expanded = book
+            .expand(Recipient::new(" Net ", Field::Cc, "configured"))
+            .unwrap();
+        let expanded: Vec<_> = expanded
+            .iter()
+            .map(|r| (r.address.as_str(), r.field, r.reason.as_str()))
+            .collect();
+        assert_eq!(
+            expanded,
+            [
+                ("netdev@example.com", Field::Cc, "configured (alias `Net`)"),
+                (
+                    "Main Tainer <maint@example.com>",
+                    Field::Cc,
+                    "configured (alias `Net`) (alias `maint`)"
+                ),
+            ]
+        );
+
+        let plain = book
+            .expand(Recipient::new("a@example.com", Field::To, "configured"))
+            .unwrap();
+        assert_eq!(plain.len(), 1);
+        assert_eq!(plain[0].address, "a@example.com");
+    }
+
+    #[test]
+    fn expand_rejects_cycles() {
+        let book = address_book(&[
+            ("loop", &["loop"]),
+            ("a", &["b@example.com", "b"]),
+            ("b", &["a"]),
+        ]);
+
+        for alias in ["loop", "a"] {
+            let error = book
+                .expand(Recipient::new(alias, Field::To, "configured"))
+                .unwrap_err();
+            assert!(error.to_string().contains("refers to itself"), "{error}");
+        }
+    }
+
+    #[test]
+    fn validate_mailboxes() {
+        for valid in [
+            "a@b.example",
+            "Name <a@b.example>",
+            "\"Doe, John\" <john@example.com>",
+            "  spaced@example.com  ",
+        ] {
+            assert!(validate_address(valid).is_ok(), "{valid} is valid");
+        }
+
+        for (invalid, message) in [
+            ("Name a@b.example>", "unmatched `>`"),
+            ("\"Name <a@b.example>", "unterminated quote"),
+            ("netdev", "neither an address nor a known alias"),
+            ("Name <not an@address>", "not a valid address"),
+        ] {
+            let error = validate_address(invalid).unwrap_err().to_string();
+            assert!(error.contains(message), "{invalid}: {error}");
+        }
+    }
+
+    #[test]
+    fn render_omits_author_when_sender() {
+        let emails = prepare(&[]);
+        let rendered = emails[0].render();
+
+        assert!(!rendered.contains("In-Reply-To"));
+        assert!(!rendered.contains("\nTo:"));
+        assert!(rendered.ends_with("\n\nCover letter\n"));
+    }
+}
@@ -1,21 +1,101 @@
 use std::{
+    collections::BTreeSet,
     fs::OpenOptions,
-    io::Write,
+    io::{IsTerminal, Write},
     ops::Deref,
     path::{Path, PathBuf},
 };
 
-use clap::{Args, Parser, Subcommand};
+use chrono::{DateTime, FixedOffset};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use config::Config;
 use directories::ProjectDirs;
 use miette::{miette, Context, IntoDiagnostic, Result};
 
+use mail::{AddressBook, Field, Recipient};
+use maintainers::{Maintainers, MaintainersConfig};
+use metadata::{EmailRecord, RebaseRecord, SeriesMetadata};
+use outbox::OutboxItem;
+use store::{PatchStore, RemovalReason, Series, StoreLocation};
 use temp_dir::TempDir;
+use transport::{SmtpConfig, TransportKind};
+use upstream::{Change, Upstream};
 use utils::OptExt;
 
+mod mail;
+mod maintainers;
+mod metadata;
+mod outbox;
+mod patch_id;
+mod store;
+mod sync;
+mod transport;
+mod upstream;
 mod utils;
+mod worktree;
+
+/// The cover letter of a series, as edited by the user
+///
+/// It starts with a `Title: ` line, optionally followed by `To: ` and `Cc: ` lines, and the rest
+/// is the body of the cover letter. Lines starting with `TODO:` are notes kept out of the body.
+struct CoverLetter<'a> {
+    title: &'a str,
+    to: Vec<String>,
+    cc: Vec<String>,
+    todos: Vec<&'a str>,
+    body: String,
+}
+
+impl<'a> CoverLetter<'a> {
+    fn parse(content: &'a str) -> Result<Self> {
+        let Some((title, mut body)) = content.split_once('\n') else {
+            return Err(miette!("Missing title newline"));
+        };
+
+        let Some(title) = title.strip_prefix("Title: ") else {
+            return Err(miette!("Missing `Title: ` prefix"));
+        };
+
+        let mut to = Vec::new();
+        let mut cc = Vec::new();
+        loop {
+            let (line, rest) = body.split_once('\n').unwrap_or((body, ""));
+            if let Some(addresses) = line.strip_prefix("To:") {
+                to.extend(mail::split_addresses(addresses));
+            } else if let Some(addresses) = line.strip_prefix("Cc:") {
+                cc.extend(mail::split_addresses(addresses));
+            } else {
+                break;
+            }
+            body = rest;
+        }
+
+        let (todos, body): (Vec<_>, Vec<_>) = body.lines().partition(|l| l.starts_with("TODO:"));
+
+        Ok(Self {
+            title,
+            to,
+            cc,
+            todos: todos
+                .into_iter()
+                .map(|t| t.trim_start_matches("TODO:").trim())
+                .collect(),
+            body: body.join("\n"),
+        })
+    }
+
+    fn read(series: &Series) -> Result<Option<String>> {
+        let path = series.cover_letter_path();
+        if !path.exists() {
+            return Ok(None);
+        }
 
-const COVER_LETTER_NAME: &str = "cover-letter";
+        std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err("Could not read the cover letter")
+            .map(Some)
+    }
+}
 
 #[derive(Parser, Debug)]
 struct Arg {
@@ -39,114 +119,2113 @@ enum Command {
     List(List),
     /// Send a patch series by mail
     Send(Send),
-    /// Delete a series
+    /// Delete a series, archiving it unless purged
     Delete(Delete),
+    /// Manage the emails queued for sending
+    Outbox(Outbox),
+    /// Import the replies to a series from an mbox
+    Import(Import),
+    /// Show the state of a series compared to its latest version
+    Status(Status),
+    /// Show a stored version of a series
+    Show(Show),
+    /// Show the differences between two stored versions of a series
+    Diff(Diff),
+    /// Move the patch store to another location
+    Migrate(Migrate),
+    /// Mirror the patch store through a git ref, to share it between machines
+    Sync(SyncStore),
+    /// Move a series to the archive
+    Archive(Archive),
+    /// Restore an archived series
+    Restore(Restore),
+    /// Archive or delete the series merged upstream
+    Prune(Prune),
+    /// Rename a series, its branch and its stored versions
+    Rename(Rename),
+    /// Rebase a series and the series stacked on it onto their updated base
+    Rebase(Rebase),
+    /// Recreate a branch from a stored version
+    Checkout(Checkout),
 }
 
 #[derive(Args, Debug)]
-struct Delete {
-    /// Only delete the local branch of the series
-    #[arg(short, long)]
-    local_only: bool,
-    /// Force the deletion of the branch (-D)
-    #[arg(short, long)]
-    force: bool,
-    /// Branch to delete (defaults to the current branch)
-    branch: Option<String>,
+struct SyncStore {
+    #[command(subcommand)]
+    command: SyncCommand,
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Remote to sync with (defaults to ${config.sync.remote} or ${config.remote})"
+    )]
+    remote: Option<String>,
 }
 
-impl Delete {
+#[derive(Subcommand, Debug)]
+enum SyncCommand {
+    /// Merge the store of the remote into the local one and publish the result
+    Push,
+    /// Merge the store of the remote into the local one
+    Pull,
+}
+
+impl SyncStore {
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let remote = match self.remote.or(config.sync.remote.clone()) {
+            Some(remote) => remote,
+            None => config.remote(&git_cd, &git_cd(&["branch", "--show-current"])?),
+        };
+
+        let fetched = sync::fetch(&git_cd, &remote)?;
+        match &fetched {
+            None => println!("{remote} has no mirrored store yet"),
+            Some(commit) => {
+                let updated = sync::merge(&git_cd, store, commit)?;
+                println!("Merged {updated} versions from {remote}");
+            }
+        }
+
+        let commit = sync::commit_store(&git_cd, store, fetched.as_deref())?;
+
+        if let SyncCommand::Push = self.command {
+            if fetched.as_deref() == Some(&commit) {
+                println!("{remote} is up to date");
+            } else {
+                git_cd(&[
+                    "push",
+                    &remote,
+                    &format!("{}:{}", sync::STORE_REF, sync::STORE_REF),
+                ])
+                .wrap_err("Could not push the store, pull before pushing again")?;
+                println!("Pushed the store to {remote}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Migrate {
+    /// New location of the patch store
+    #[arg(value_enum)]
+    to: StoreLocation,
+}
+
+impl Migrate {
+    pub fn run(
+        self,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+        dest: &Path,
+    ) -> Result<()> {
+        if store.dir() == dest {
+            println!("The patch store is already in {dest:?}");
+            return Ok(());
+        }
+
+        store.move_to(dest)?;
+        git_cd(&["config", "--local", "gsm.store", self.to.name()])?;
+
+        println!("Moved the patch store to {dest:?}");
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Diff {
+    /// Series to compare the versions of
+    series: String,
+    /// Older version
+    #[arg(value_parser = parse_version)]
+    from: u64,
+    /// Newer version
+    #[arg(value_parser = parse_version)]
+    to: u64,
+    #[arg(long, help = "Show a range-diff instead of an interdiff")]
+    range_diff: bool,
+    #[arg(
+        short = 'B',
+        long,
+        help = "Commit to apply versions on when their base is unknown (defaults to ${config.interdiff_base})"
+    )]
+    base: Option<String>,
+}
+
+impl Diff {
+    /// The base and tip commits of a stored version
+    ///
+    /// The commits recorded when formatting are used when they still exist and match the stored
+    /// patches, otherwise the patches are applied in a temporary worktree.
+    fn version_commits(
+        &self,
+        config: &GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        series: &Series,
+        version: u64,
+    ) -> Result<(String, String)> {
+        let patches = series.existing_version(version)?.patches()?;
+        let metadata = SeriesMetadata::load(series.dir())?;
+        let version_metadata = metadata.version(version);
+
+        let base = match (&self.base, version_base(&git_cd, series, version)?) {
+            (Some(base), _) => base.clone(),
+            (None, Some(base)) => base,
+            (None, None) => config.upstream(&git_cd, series.name()),
+        };
+        let base = git_cd(&["rev-parse", "--verify", &format!("{base}^{{commit}}")])
+            .wrap_err_with(|| format!("Could not find the base of v{version}"))?;
+
+        if let Some(head) = version_metadata.and_then(|v| v.head.as_ref()) {
+            let stored: Vec<_> = patch_id::of_patches(&patches)?
+                .into_iter()
+                .map(|p| p.id)
+                .collect();
+
+            if let Ok(recorded) = patch_id::of_range(&git_cd, &format!("{base}..{head}")) {
+                if recorded.into_iter().map(|p| p.id).eq(stored) {
+                    return Ok((base, head.clone()));
+                }
+            }
+        }
+
+        let tip = worktree::apply_patches(&git_cd, &base, &patches)
+            .wrap_err_with(|| format!("Could not rebuild v{version}"))?;
+
+        Ok((base, tip))
+    }
+
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let series = store.existing_series(&self.series)?;
+
+        let cover_letters = TempDir::new()
+            .into_diagnostic()
+            .wrap_err("Could not create cover letter directory")?;
+        for version in [self.from, self.to] {
+            let cover_letter = series
+                .existing_version(version)?
+                .cover_letter()?
+                .map(|p| mail::Patch::read(&p))
+                .transpose()?;
+
+            let text = match cover_letter {
+                Some(c) => format!("Title: {}\n\n{}", c.subject(), c.message().trim()),
+                None => String::new(),
+            };
+
+            std::fs::write(cover_letters.child(format!("v{version}")), text + "\n")
+                .into_diagnostic()
+                .wrap_err("Could not write cover letter")?;
+        }
+
+        // `git diff --no-index` exits with 1 when the files differ
+        let cover_diff = duct::cmd!(
+            "git",
+            "diff",
+            "--no-index",
+            "--no-color",
+            format!("v{}", self.from),
+            format!("v{}", self.to)
+        )
+        .dir(cover_letters.path())
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .into_diagnostic()
+        .wrap_err("failed to launch git")?;
+
+        match String::from_utf8_lossy(&cover_diff.stdout).trim_end() {
+            "" => println!("Cover letter unchanged\n"),
+            diff => println!("{diff}\n"),
+        }
+
+        let (from_base, from) = self.version_commits(&config, &git_cd, &series, self.from)?;
+        let (to_base, to) = self.version_commits(&config, &git_cd, &series, self.to)?;
+
+        let diff = match self.range_diff {
+            true => git_cd(&[
+                "range-diff",
+                "--no-color",
+                &format!("{from_base}..{from}"),
+                &format!("{to_base}..{to}"),
+            ])?,
+            false => git_cd(&["diff", "--no-color", &from, &to])?,
+        };
+
+        match diff.as_str() {
+            "" => println!("No changes between v{} and v{}", self.from, self.to),
+            diff => println!("{diff}"),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Show {
+    /// Series to show
+    series: String,
+    /// Version to show, defaults to the latest one
+    #[arg(value_parser = parse_version)]
+    version: Option<u64>,
+    #[arg(
+        long,
+        help = "Show the patch with this number instead of the whole version"
+    )]
+    patch: Option<usize>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ShownVersion {
+    series: String,
+    version: u64,
+    title: String,
+    cover_letter: String,
+    shortlog: Vec<ShortlogEntry>,
+    diffstat: String,
+    patches: Vec<ShownPatch>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ShortlogEntry {
+    author: String,
+    subjects: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ShownPatch {
+    number: usize,
+    file: String,
+    subject: String,
+    author: String,
+    date: String,
+    /// Message-ID the patch was sent with, if it was
+    message_id: Option<String>,
+    diffstat: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<String>,
+}
+
+impl Show {
+    pub fn run(
+        self,
+        _config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let series = store.existing_series(&self.series)?;
+        let version = match self.version {
+            Some(version) => series.existing_version(version)?,
+            None => series
+                .latest()?
+                .ok_or(miette!("No version of `{}` was formatted", self.series))?,
+        };
+
+        let metadata = SeriesMetadata::load(series.dir())?;
+        let version_metadata = metadata.version(version.number);
+
+        let mut cover_letter = None;
+        let mut patches = Vec::new();
+        for path in version.patches()? {
+            let patch = mail::Patch::read(&path)?;
+            let file = path
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            if file.ends_with("cover-letter.patch") {
+                cover_letter = Some(patch);
+                continue;
+            }
+
+            let show_patch = self.patch == Some(patches.len() + 1);
+            patches.push(ShownPatch {
+                number: patches.len() + 1,
+                subject: patch.subject(),
+                author: patch.header("From").unwrap_or_default().to_string(),
+                date: patch.header("Date").unwrap_or_default().to_string(),
+                message_id: version_metadata
+                    .and_then(|v| v.sent(&file))
+                    .map(|e| e.message_id.clone()),
+                diffstat: patch.diffstat().to_string(),
+                message: show_patch.then(|| patch.message().trim().to_string()),
+                diff: show_patch.then(|| patch.diff().to_string()),
+                file,
+            });
+        }
+
+        if let Some(number) = self.patch {
+            let Some(patch) = patches.into_iter().find(|p| p.number == number) else {
+                return Err(miette!(
+                    "v{} of `{}` has no patch {number}",
+                    version.number,
+                    self.series
+                ));
+            };
+
+            match self.format {
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&patch)
+                        .into_diagnostic()
+                        .wrap_err("Could not serialize patch")?
+                ),
+                OutputFormat::Text => {
+                    let mut text = format!(
+                        "From: {}\nDate: {}\nSubject: {}\n",
+                        patch.author, patch.date, patch.subject
+                    );
+                    if let Some(message_id) = &patch.message_id {
+                        text += &format!("Message-ID: {message_id}\n");
+                    }
+                    text.push('\n');
+                    if let Some(message) = patch.message.filter(|m| !m.is_empty()) {
+                        text += &format!("{message}\n\n");
+                    }
+                    text += &format!("---\n{}\n\n", patch.diffstat);
+                    text += &patch.diff.unwrap_or_default();
+
+                    page(&git_cd, &text)?;
+                }
+            }
+
+            return Ok(());
+        }
+
+        let mut shortlog: Vec<ShortlogEntry> = Vec::new();
+        for patch in &patches {
+            let author = mail::display_name(&patch.author).to_string();
+            match shortlog.iter_mut().find(|e| e.author == author) {
+                Some(entry) => entry.subjects.push(patch.subject.clone()),
+                None => shortlog.push(ShortlogEntry {
+                    author,
+                    subjects: vec![patch.subject.clone()],
+                }),
+            }
+        }
+
+        // The cover letter is the blurb followed by the shortlog and diffstat from `format-patch`
+        let (title, text, diffstat) = match &cover_letter {
+            None => (String::new(), String::new(), String::new()),
+            Some(cover_letter) => {
+                let message = cover_letter.message();
+                let shortlog_start = shortlog
+                    .first()
+                    .and_then(|e| message.find(&format!("\n{} (", e.author)))
+                    .unwrap_or(message.len());
+
+                let diffstat = message[shortlog_start..]
+                    .split("\n\n")
+                    .find(|p| p.lines().any(|l| l.contains(" changed")))
+                    .unwrap_or_default();
+
+                (
+                    cover_letter.subject(),
+                    message[..shortlog_start].trim().to_string(),
+                    diffstat.trim_end().to_string(),
+                )
+            }
+        };
+
+        let shown = ShownVersion {
+            series: self.series,
+            version: version.number,
+            title,
+            cover_letter: text,
+            shortlog,
+            diffstat,
+            patches,
+        };
+
+        match self.format {
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&shown)
+                    .into_diagnostic()
+                    .wrap_err("Could not serialize version")?
+            ),
+            OutputFormat::Text => {
+                println!("Series: {} v{}", shown.series, shown.version);
+                println!("Title: {}", shown.title);
+                if !shown.cover_letter.is_empty() {
+                    println!();
+                    println!("{}", shown.cover_letter);
+                }
+
+                for entry in &shown.shortlog {
+                    println!();
+                    println!("{} ({}):", entry.author, entry.subjects.len());
+                    for subject in &entry.subjects {
+                        println!("  {subject}");
+                    }
+                }
+
+                if !shown.diffstat.is_empty() {
+                    println!();
+                    println!("{}", shown.diffstat);
+                }
+
+                println!();
+                for patch in &shown.patches {
+                    let sent = match patch.message_id {
+                        Some(_) => "sent",
+                        None => "unsent",
+                    };
+                    println!(
+                        "[{}/{}] {} ({sent})",
+                        patch.number,
+                        shown.patches.len(),
+                        patch.subject
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Status {
+    /// Series to inspect (defaults to the current branch)
+    series: Option<String>,
+    #[arg(long, help = "Also show the patches already applied upstream")]
+    applied: bool,
+}
+
+impl Status {
     pub fn run(
         self,
         config: GsmConfig,
         git_cd: impl Fn(&[&str]) -> Result<String>,
-        patch_dir: &Path,
+        store: &PatchStore,
     ) -> Result<()> {
         let current_branch = git_cd(&["branch", "--show-current"])?;
+        let branch = self
+            .series
+            .as_ref()
+            .try_m_unwrap_or_else(|| Ok(&current_branch))?;
+
+        println!("Series: {branch}");
+
+        let series = store.series(branch);
+        let upstream = config.upstream(&git_cd, branch);
+        let applied_upstream = self.applied.then_some(upstream.as_str());
+        let summary = SeriesSummary::new(&series, false, applied_upstream, &git_cd)?;
+
+        match &summary {
+            None => {
+                println!("No version was formatted yet");
+                println!("Next version: v1");
+            }
+            Some(summary) => {
+                let formatted = summary
+                    .formatted
+                    .map(|f| format!(", formatted on {}", f.format("%Y-%m-%d %H:%M")))
+                    .unwrap_or_default();
+                println!(
+                    "Latest version: v{} ({}{formatted})",
+                    summary.latest, summary.sent
+                );
+                println!("Next version: v{}", summary.latest + 1);
+
+                let metadata = SeriesMetadata::load(series.dir())?;
+                let latest = metadata.version(summary.latest);
+                let patches = series.version(summary.latest).patches()?;
+
+                let head =
+                    git_cd(&["rev-parse", "--verify", "--quiet", &metadata.tip(branch)]).ok();
+
+                let base = version_base(&git_cd, &series, summary.latest)?;
+
+                match &head {
+                    None => println!("Branch: deleted"),
+                    Some(head) if latest.and_then(|v| v.head.as_ref()) == Some(head) => {
+                        println!("Branch: unchanged since v{}", summary.latest)
+                    }
+                    Some(head) => {
+                        let contains = |commit: &str| {
+                            git_cd(&["merge-base", "--is-ancestor", commit, head]).is_ok()
+                        };
+
+                        // The series starts where the branch forks from upstream, falling back to
+                        // the recorded base when there is no upstream to compare to
+                        let fork_point = git_cd(&["merge-base", &upstream, head]).ok();
+
+                        // A stacked series starts at the tip of its parent instead, which it was
+                        // based on when formatted
+                        let parent_tip = match &metadata.parent {
+                            Some(parent) => series_tip(&git_cd, store, parent)?,
+                            None => None,
+                        };
+
+                        let rebased = match (&metadata.parent, &base, &parent_tip, &fork_point) {
+                            (_, None, _, _) => None,
+                            (Some(_), Some(base), Some(parent_tip), _) if base == parent_tip => {
+                                Some(false)
+                            }
+                            (Some(_), Some(_), Some(parent_tip), _) if contains(parent_tip) => {
+                                Some(true)
+                            }
+                            (Some(_), Some(base), _, _) => Some(!contains(base)),
+                            (None, Some(base), _, Some(fork_point)) => Some(base != fork_point),
+                            (None, Some(base), _, None) => Some(!contains(base)),
+                        };
+
+                        let range_base = match (&metadata.parent, &parent_tip, &base, &fork_point) {
+                            (Some(_), Some(parent_tip), _, _) if contains(parent_tip) => {
+                                parent_tip.clone()
+                            }
+                            (Some(_), _, Some(base), _) if contains(base) => base.clone(),
+                            (_, _, _, Some(fork_point)) => fork_point.clone(),
+                            (None, _, Some(base), None) if rebased == Some(false) => base.clone(),
+                            _ => upstream,
+                        };
+
+                        let stored = patch_id::of_patches(&patches)?;
+                        let current =
+                            patch_id::of_range(&git_cd, &format!("{range_base}..{head}"))?;
+
+                        let unchanged = current
+                            .iter()
+                            .filter(|c| stored.iter().any(|s| s.id == c.id))
+                            .count();
+                        let dropped = stored
+                            .iter()
+                            .filter(|s| !current.iter().any(|c| c.id == s.id))
+                            .count();
+
+                        match (unchanged == current.len(), dropped) {
+                            (true, 0) => println!(
+                                "Branch: patches unchanged since v{}",
+                                summary.latest
+                            ),
+                            _ => println!(
+                                "Branch: changed since v{} ({unchanged} unchanged, {} new or modified, {dropped} dropped patches)",
+                                summary.latest,
+                                current.len() - unchanged,
+                            ),
+                        }
+
+                        match rebased {
+                            Some(true) => println!(
+                                "Rebased: yes, v{} was based on {}",
+                                summary.latest,
+                                base.as_deref().unwrap_or_default()
+                            ),
+                            Some(false) => println!("Rebased: no"),
+                            None => println!("Rebased: unknown"),
+                        }
+                    }
+                }
+
+                if let Some(ci) = latest.and_then(|v| v.ci.as_ref()) {
+                    println!("CI: {ci}");
+                }
+
+                if let Some(parent) = &metadata.parent {
+                    let based_on = latest.and_then(|v| v.parent_version);
+                    let parent_latest = store.series(parent).latest()?.map(|v| v.number);
+                    match based_on {
+                        Some(based_on) => println!("Parent: {parent} v{based_on}"),
+                        None => println!("Parent: {parent}"),
+                    }
+                    if let (Some(based_on), Some(parent_latest)) = (based_on, parent_latest) {
+                        if parent_latest > based_on {
+                            eprintln!(
+                                "{:?}",
+                                miette!(
+                                    severity = miette::Severity::Warning,
+                                    help = "Rebase the branch on it and format a new version",
+                                    "{parent} has a newer version v{parent_latest} than the v{based_on} this series is based on"
+                                )
+                            );
+                        }
+                    }
+                }
+
+                match &summary.applied {
+                    None if !self.applied => (),
+                    None => println!("Applied upstream: unknown"),
+                    Some(applied) if applied.is_empty() => println!("Applied upstream: none"),
+                    Some(applied) => {
+                        println!(
+                            "Applied upstream: {} of {} patches of v{}",
+                            applied.len(),
+                            summary.patch_count,
+                            summary.latest
+                        );
+                        for subject in applied {
+                            println!("  - {subject}");
+                        }
+                    }
+                }
+            }
+        }
+
+        let cover_letter = CoverLetter::read(&series)?;
+        if let Some(cover_letter) = cover_letter
+            .as_deref()
+            .map(CoverLetter::parse)
+            .transpose()?
+        {
+            if !cover_letter.todos.is_empty() {
+                println!("TODO:");
+                for todo in cover_letter.todos {
+                    println!("  - {todo}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Import {
+    /// mbox containing the replies, for example downloaded from a mailing list archive
+    mbox: PathBuf,
+    /// Series the replies belong to (defaults to the current branch)
+    series: Option<String>,
+}
 
+impl Import {
+    pub fn run(
+        self,
+        _config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let current_branch = git_cd(&["branch", "--show-current"])?;
         let branch = self
-            .branch
+            .series
             .as_ref()
             .try_m_unwrap_or_else(|| Ok(&current_branch))?;
 
-        let has_remote = git_cd(&["rev-parse", "@{u}"]).is_ok();
-        if has_remote && !self.local_only {
-            println!("Removing branch from remote repository");
-            git_cd(&["push", "-d", "origin", branch])?;
+        let series = store.existing_series(branch)?;
+        let mut metadata = SeriesMetadata::load(series.dir())?;
+
+        let mbox = std::fs::read_to_string(&self.mbox)
+            .into_diagnostic()
+            .wrap_err("Could not read mbox")?;
+
+        let mut imported = 0;
+        for message in mail::split_mbox(&mbox) {
+            let reply = mail::Patch::parse(&message).wrap_err("Could not parse mbox message")?;
+
+            let parents = ["In-Reply-To", "References"]
+                .iter()
+                .filter_map(|h| reply.header(h))
+                .flat_map(|h| h.split_whitespace())
+                .collect::<Vec<_>>();
+
+            let Some(version) = metadata
+                .versions
+                .iter_mut()
+                .find(|v| parents.iter().any(|p| v.has_message(p)))
+            else {
+                continue;
+            };
+
+            imported += 1;
+            for participant in reply.addresses("From") {
+                metadata::add_address(&mut version.participants, &participant);
+            }
+            for (_, reviewer) in reply.review_trailers() {
+                metadata::add_address(&mut version.reviewers, &reviewer);
+            }
+        }
+
+        metadata.save(series.dir())?;
+        println!("Imported {imported} replies to {branch}");
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Outbox {
+    #[command(subcommand)]
+    command: OutboxCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum OutboxCommand {
+    /// List the queued series
+    #[command(alias = "ls")]
+    List,
+    /// Send the queued series that are due
+    Flush {
+        #[arg(short, long, help = "Also send the series scheduled in the future")]
+        all: bool,
+    },
+    /// Remove a series from the outbox without sending it
+    Drop { id: u64 },
+}
+
+impl Outbox {
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let outbox = outbox::Outbox::new(store.dir());
+
+        match self.command {
+            OutboxCommand::List => {
+                for item in outbox.items()? {
+                    let at = match item.at {
+                        Some(at) if !item.is_due() => format!("at {at}"),
+                        _ => String::from("due"),
+                    };
+                    println!(" - {}: {} v{} ({at})", item.id, item.series, item.version);
+                }
+            }
+            OutboxCommand::Flush { all } => {
+                let mut failed = 0;
+                for item in outbox.items()? {
+                    if !all && !item.is_due() {
+                        continue;
+                    }
+
+                    println!("Sending {} v{}", item.series, item.version);
+                    let send = Send {
+                        version: Some(item.version),
+                        resume: true,
+                        force: false,
+                        throttle: item.throttle,
+                        transport: item.transport,
+                        sink: item.sink.clone(),
+                        dry_run: false,
+                        cc_reviewers: false,
+                        no_cc_reviewers: false,
+                        to: item.to.clone(),
+                        cc: item.cc.clone(),
+                        queue: false,
+                        at: None,
+                        series: Some(item.series.clone()),
+                    };
+
+                    match send.run(config.clone(), &git_cd, store) {
+                        Ok(()) => outbox.remove(item.id)?,
+                        Err(e) => {
+                            failed += 1;
+                            eprintln!("{e:?}");
+                        }
+                    }
+                }
+
+                if failed != 0 {
+                    return Err(miette!("{failed} queued series could not be sent"));
+                }
+            }
+            OutboxCommand::Drop { id } => outbox.remove(id)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Delete {
+    /// Only delete the local branch of the series
+    #[arg(short, long)]
+    local_only: bool,
+    /// Force the deletion of the branch (-D)
+    #[arg(short, long)]
+    force: bool,
+    /// Remove the series from the store instead of archiving it
+    #[arg(long)]
+    purge: bool,
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    yes: bool,
+    /// Print what would be done without doing it
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Branch to delete (defaults to the current branch)
+    branch: Option<String>,
+}
+
+/// Print `text` through the pager of git when stdout is a terminal
+fn page(git_cd: impl Fn(&[&str]) -> Result<String>, text: &str) -> Result<()> {
+    let pager = match std::io::stdout().is_terminal() {
+        true => git_cd(&["var", "GIT_PAGER"])
+            .ok()
+            .or_else(|| std::env::var("PAGER").ok()),
+        false => None,
+    };
+    let Some(pager) = pager.filter(|p| !p.is_empty() && p != "cat") else {
+        print!("{text}");
+        return Ok(());
+    };
+
+    // Like git, the pager is a shell command and less is told to quit on short output
+    let mut command = std::process::Command::new("sh");
+    command.args(["-c", &pager]);
+    command.stdin(std::process::Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = command
+        .spawn()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not run the pager `{pager}`"))?;
+
+    // The pager may be quit before reading everything
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child
+        .wait()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not run the pager `{pager}`"))?;
+
+    Ok(())
+}
+
+/// Ask the user a yes/no question, defaulting to no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout()
+        .flush()
+        .into_diagnostic()
+        .wrap_err("Could not write the question")?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .into_diagnostic()
+        .wrap_err("Could not read the answer")?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// The commit a stored version is based on: the recorded one, else the parent of its first patch
+fn version_base(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    series: &Series,
+    version: u64,
+) -> Result<Option<String>> {
+    let metadata = SeriesMetadata::load(series.dir())?;
+    let base = match metadata.version(version).and_then(|v| v.base.clone()) {
+        Some(base) => Some(base),
+        None => patch_id::patch_commits(&series.existing_version(version)?.patches()?)?
+            .first()
+            .map(|first| format!("{first}^")),
+    };
+
+    Ok(base.and_then(|base| {
+        git_cd(&[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{base}^{{commit}}"),
+        ])
+        .ok()
+    }))
+}
+
+/// The commit at the tip of a series, if its branch or range still resolves
+fn series_tip(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    store: &PatchStore,
+    name: &str,
+) -> Result<Option<String>> {
+    let metadata = SeriesMetadata::load(store.series(name).dir())?;
+
+    Ok(git_cd(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{}^{{commit}}", metadata.tip(name)),
+    ])
+    .ok())
+}
+
+/// Subjects of the patches of a stored version already applied in `upstream`
+///
+/// Returns `None` when the base of the version is unknown.
+fn applied_patches(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    series: &Series,
+    version: u64,
+    upstream: &str,
+) -> Result<Option<Vec<String>>> {
+    let Some(base) = version_base(&git_cd, series, version)? else {
+        return Ok(None);
+    };
+
+    let merged = Upstream::load(&git_cd, &format!("{base}..{upstream}"))?;
+    let changes = upstream::of_patches(&series.existing_version(version)?.patches()?)?;
+
+    Ok(Some(
+        changes
+            .into_iter()
+            .filter(|c| merged.contains(c))
+            .map(|c| c.subject)
+            .collect(),
+    ))
+}
+
+/// Fetch `upstream` when it is a remote-tracking branch
+fn fetch_upstream(git_cd: impl Fn(&[&str]) -> Result<String>, upstream: &str) -> Result<()> {
+    let remotes = git_cd(&["remote"])?;
+    if let Some((remote, branch)) = upstream.split_once('/') {
+        if remotes.lines().any(|r| r == remote) {
+            println!("Fetching {upstream}");
+            git_cd(&["fetch", remote, branch])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Ref keeping the tip of the branch of an archived series
+fn archived_tip_ref(series: &str) -> String {
+    format!("refs/gsm/archive/{series}")
+}
+
+/// Mark a series brought back under its name, so older removals synced from elsewhere keep it
+fn revive_series(series: &Series) -> Result<()> {
+    let mut metadata = SeriesMetadata::load(series.dir())?;
+    metadata.revived = Some(chrono::Local::now().fixed_offset());
+    metadata.save(series.dir())
+}
+
+/// Move a series to the archive, keeping `tip` under a ref if given
+fn archive_series(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    store: &PatchStore,
+    name: &str,
+    tip: Option<&str>,
+) -> Result<()> {
+    let series = store.existing_series(name)?;
+    series
+        .move_to(&store.archive().series(name))
+        .wrap_err_with(|| format!("Could not archive `{name}`, is it already archived?"))?;
+
+    if let Some(tip) = tip {
+        git_cd(&["update-ref", &archived_tip_ref(name), tip])?;
+    }
+    store.bury(name, RemovalReason::Archived)?;
+
+    println!("Archived {name}");
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+struct Archive {
+    /// Keep the tip of the branch under refs/gsm/archive, to restore it even if deleted
+    #[arg(short, long)]
+    keep_tip: bool,
+    /// Series to archive (defaults to the current branch)
+    series: Option<String>,
+}
+
+impl Archive {
+    pub fn run(
+        self,
+        _config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let current_branch = git_cd(&["branch", "--show-current"])?;
+        let series = self
+            .series
+            .as_ref()
+            .try_m_unwrap_or_else(|| Ok(&current_branch))?;
+
+        let tip = match self.keep_tip {
+            true => Some(
+                git_cd(&["rev-parse", "--verify", &format!("refs/heads/{series}")])
+                    .wrap_err_with(|| format!("Could not find the branch {series}"))?,
+            ),
+            false => None,
+        };
+
+        archive_series(&git_cd, store, series, tip.as_deref())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Restore {
+    /// Archived series to restore, the archived series are listed if not given
+    series: Option<String>,
+}
+
+impl Restore {
+    pub fn run(
+        self,
+        _config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let archive = store.archive();
+
+        let Some(name) = self.series else {
+            for series in archive.all_series()? {
+                let tip = match git_cd(&[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &archived_tip_ref(series.name()),
+                ]) {
+                    Ok(_) => " (with branch tip)",
+                    Err(_) => "",
+                };
+                println!("{}{tip}", series.name());
+            }
+            return Ok(());
+        };
+
+        let archived = archive
+            .existing_series(&name)
+            .wrap_err("No such archived series")?;
+        if store.series(&name).exists() {
+            return Err(miette!(
+                "The series `{name}` exists, archive or delete it before restoring"
+            ));
+        }
+        archived.move_to(&store.series(&name))?;
+        revive_series(&store.series(&name))?;
+        println!("Restored {name}");
+
+        let tip_ref = archived_tip_ref(&name);
+        if let Ok(tip) = git_cd(&["rev-parse", "--verify", "--quiet", &tip_ref]) {
+            let branch_ref = format!("refs/heads/{name}");
+            match git_cd(&["rev-parse", "--verify", "--quiet", &branch_ref]) {
+                Ok(_) => {
+                    println!("The branch {name} exists, keeping the archived tip in {tip_ref}")
+                }
+                Err(_) => {
+                    git_cd(&["branch", &name, &tip])?;
+                    git_cd(&["update-ref", "-d", &tip_ref])?;
+                    println!("Recreated the branch {name}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The removal of a series: its branches, then its stored versions
+struct Removal {
+    name: String,
+    tip: Option<String>,
+    commands: Vec<Vec<String>>,
+    summary: Vec<String>,
+    series: Series,
+    purge: bool,
+}
+
+impl Removal {
+    /// The remote a branch is pushed to, `None` for branches only tracking local ones
+    fn push_remote(
+        config: &GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        name: &str,
+    ) -> Option<String> {
+        let tracked = git_cd(&["config", "--get", &format!("branch.{name}.remote")]).ok();
+        match git_cd(&["config", "--get", &format!("branch.{name}.pushRemote")])
+            .or_else(|_| git_cd(&["config", "--get", "remote.pushDefault"]))
+        {
+            Ok(remote) => Some(remote),
+            // A branch tracking a local branch has no remote counterpart
+            Err(_) if config.remote.is_none() && tracked.as_deref() == Some(".") => None,
+            Err(_) => Some(config.remote(&git_cd, name)),
+        }
+        .filter(|remote| remote != ".")
+    }
+
+    /// The remote whose default branch is `name`, which is never deleted, locally or remotely
+    fn default_branch_of(
+        config: &GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        name: &str,
+    ) -> Option<String> {
+        std::iter::once(config.remote(&git_cd, name))
+            .chain(Self::push_remote(config, &git_cd, name))
+            .find(|remote| config.default_branch(&git_cd, remote) == name)
+    }
+
+    fn plan(
+        config: &GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+        name: &str,
+        local_only: bool,
+        force: bool,
+        purge: bool,
+    ) -> Result<Self> {
+        let tip = git_cd(&["rev-parse", "--verify", &format!("refs/heads/{name}")]).ok();
+
+        let mut commands: Vec<Vec<String>> = Vec::new();
+        let mut summary = Vec::new();
+
+        if let Some(tip) = &tip {
+            if let Some(remote) = Self::default_branch_of(config, &git_cd, name) {
+                return Err(miette!(
+                    help = format!("Use `gsm archive {name}` to only archive its series"),
+                    "Refusing to delete {name}, the default branch of {remote}"
+                ));
+            }
+
+            summary.push(format!("local branch {name} ({})", &tip[..12]));
+
+            // Only the branch of the same name on the remote the branch is pushed to is deleted,
+            // never the branch it tracks, which is often the default branch
+            let remote = Self::push_remote(config, &git_cd, name);
+            if let Some(remote) = remote.filter(|_| !local_only) {
+                let merge = git_cd(&["config", "--get", &format!("branch.{name}.merge")]).ok();
+                let default_branch = config.default_branch(&git_cd, &remote);
+                let remote_ref = format!("refs/remotes/{remote}/{name}");
+
+                if merge.as_deref() == Some(&format!("refs/heads/{default_branch}")) {
+                    println!("Keeping the remote branches of {name}, it tracks {remote}/{default_branch}");
+                } else if git_cd(&["rev-parse", "--verify", "--quiet", &remote_ref]).is_ok() {
+                    summary.push(format!("remote branch {remote}/{name}"));
+                    commands.push(
+                        ["push", "-d", &remote, &format!("refs/heads/{name}")]
+                            .map(String::from)
+                            .to_vec(),
+                    );
+                }
+            }
+
+            if git_cd(&["branch", "--show-current"])? == name {
+                let target = config.default_branch(&git_cd, &config.remote(&git_cd, name));
+                commands.push(["switch", &target].map(String::from).to_vec());
+            }
+
+            let branch_delete = match force {
+                true => "-D",
+                false => "-d",
+            };
+            commands.push(["branch", branch_delete, name].map(String::from).to_vec());
+        }
+
+        let series = store.series(name);
+        Self::check_archive(store, &series, purge)?;
+        if series.exists() {
+            let versions = series.versions()?.len();
+            summary.push(match purge {
+                true => format!("{versions} stored versions, permanently"),
+                false => format!("{versions} stored versions, moved to the archive"),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            tip,
+            commands,
+            summary,
+            series,
+            purge,
+        })
+    }
+
+    /// Fail early when the series cannot be archived, before its branches are removed
+    fn check_archive(store: &PatchStore, series: &Series, purge: bool) -> Result<()> {
+        if series.exists() && !purge && store.archive().series(series.name()).exists() {
+            return Err(miette!(
+                help = "Restore or purge the archived series first, or pass --purge",
+                "The archive already holds a series named {}",
+                series.name()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn print_summary(&self) {
+        for item in &self.summary {
+            println!("  - {item}");
+        }
+    }
+
+    fn print_commands(&self) {
+        if self.series.exists() {
+            match (self.purge, &self.tip) {
+                (true, _) => println!("rm -r {:?}", self.series.dir()),
+                (false, Some(_)) => println!("gsm archive --keep-tip {}", self.name),
+                (false, None) => println!("gsm archive {}", self.name),
+            }
+        }
+        for command in &self.commands {
+            println!("git {}", command.join(" "));
+        }
+    }
+
+    fn execute(
+        &self,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        Self::check_archive(store, &self.series, self.purge)?;
+
+        // The series goes first, an archived one can be restored along with its branch if removing
+        // the branches fails
+        match (self.series.exists(), self.purge) {
+            (false, _) => (),
+            (true, true) => {
+                self.series.remove()?;
+                store.bury(&self.name, RemovalReason::Purged)?;
+            }
+            (true, false) => archive_series(&git_cd, store, &self.name, self.tip.as_deref())?,
+        }
+
+        for command in &self.commands {
+            let command: Vec<_> = command.iter().map(|s| s.as_str()).collect();
+            if let ["switch", target] = command.as_slice() {
+                println!(
+                    "Branch {} currently checked out, switching to {target}",
+                    self.name
+                );
+            }
+            git_cd(&command)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Delete {
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let branch = self
+            .branch
+            .try_m_unwrap_or_else(|| git_cd(&["branch", "--show-current"]))?;
+
+        let tip = git_cd(&["rev-parse", "--verify", &format!("refs/heads/{branch}")])
+            .wrap_err_with(|| format!("No branch named {branch}"))?;
+
+        let removal = Removal::plan(
+            &config,
+            &git_cd,
+            store,
+            &branch,
+            self.local_only,
+            self.force,
+            self.purge,
+        )?;
+
+        println!("Deleting {branch} will remove:");
+        removal.print_summary();
+
+        // Patches applied upstream have the same patch-id as a commit of the base
+        let base = config.upstream(&git_cd, &branch);
+        match git_cd(&["cherry", &base, &tip]) {
+            Err(_) => println!("Could not check if the patches were merged in {base}"),
+            Ok(cherry) => {
+                let total = cherry.lines().count();
+                let pending = cherry.lines().filter(|l| l.starts_with('+')).count();
+                match pending {
+                    0 => println!("All the patches were merged in {base}"),
+                    _ => {
+                        println!("WARNING: {pending} of {total} patches were not merged in {base}")
+                    }
+                }
+            }
+        }
+
+        if self.dry_run {
+            removal.print_commands();
+            return Ok(());
+        }
+
+        if !self.yes && !confirm("Proceed?")? {
+            return Err(miette!("Aborted"));
+        }
+
+        removal.execute(&git_cd, store)
+    }
+}
+
+#[derive(Args, Debug)]
+struct Prune {
+    /// Keep the remote branches of the pruned series
+    #[arg(short, long)]
+    local_only: bool,
+    /// Remove the series from the store instead of archiving them
+    #[arg(long)]
+    purge: bool,
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    yes: bool,
+    /// Print what would be done without doing it
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+impl Prune {
+    /// The changes of a series and the commit they are based on
+    ///
+    /// The commits of the branch are used when it has some on top of upstream, otherwise those of
+    /// the latest version, for branches deleted or fast-forwarded upstream.
+    fn series_changes(
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        series: &Series,
+        upstream: &str,
+    ) -> Result<Option<(String, Vec<Change>)>> {
+        let name = series.name();
+        if git_cd(&["rev-parse", "--verify", &format!("refs/heads/{name}")]).is_ok() {
+            if let Ok(fork_point) = git_cd(&["merge-base", upstream, name]) {
+                let changes = upstream::of_range(&git_cd, &format!("{fork_point}..{name}"))?;
+                if !changes.is_empty() {
+                    return Ok(Some((fork_point, changes)));
+                }
+            }
+        }
+
+        let Some(latest) = series.latest()? else {
+            return Ok(None);
+        };
+
+        match version_base(&git_cd, series, latest.number)? {
+            Some(base) => Ok(Some((base, upstream::of_patches(&latest.patches()?)?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let current_branch = git_cd(&["branch", "--show-current"])?;
+        let upstream = config.upstream(&git_cd, &current_branch);
+
+        fetch_upstream(&git_cd, &upstream)?;
+
+        let mut removals = Vec::new();
+        for series in store.all_series()? {
+            let Some((base, changes)) = Self::series_changes(&git_cd, &series, &upstream)? else {
+                println!("Skipping {}: its base is unknown", series.name());
+                continue;
+            };
+
+            let merged = Upstream::load(&git_cd, &format!("{base}..{upstream}"))?;
+            if changes.is_empty() || !changes.iter().all(|c| merged.contains(c)) {
+                continue;
+            }
+
+            let branch_exists = git_cd(&[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("refs/heads/{}", series.name()),
+            ])
+            .is_ok();
+            if let Some(remote) = Removal::default_branch_of(&config, &git_cd, series.name())
+                .filter(|_| branch_exists)
+            {
+                eprintln!(
+                    "{:?}",
+                    miette!(
+                        severity = miette::Severity::Warning,
+                        help = format!("Use `gsm archive {}` to archive it", series.name()),
+                        "Skipping {}: it is the default branch of {remote}",
+                        series.name()
+                    )
+                );
+                continue;
+            }
+
+            // One series that cannot be removed does not hold back the others
+            match Removal::plan(
+                &config,
+                &git_cd,
+                store,
+                series.name(),
+                self.local_only,
+                true,
+                self.purge,
+            ) {
+                Ok(removal) => removals.push(removal),
+                Err(e) => eprintln!(
+                    "{:?}",
+                    miette!(
+                        severity = miette::Severity::Warning,
+                        "Skipping {}: {e}",
+                        series.name()
+                    )
+                ),
+            }
+        }
+
+        if removals.is_empty() {
+            println!("No series merged in {upstream}");
+            return Ok(());
+        }
+
+        println!("Series merged in {upstream}:");
+        for removal in &removals {
+            println!("{}", removal.name);
+            removal.print_summary();
+        }
+
+        if self.dry_run {
+            for removal in &removals {
+                removal.print_commands();
+            }
+            return Ok(());
+        }
+
+        if !self.yes && !confirm(&format!("Remove {} series?", removals.len()))? {
+            return Err(miette!("Aborted"));
+        }
+
+        for removal in &removals {
+            removal.execute(&git_cd, store)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Rename {
+    /// Also rename the remote branch the branch tracks
+    #[arg(short, long)]
+    remote: bool,
+    /// Current name of the series
+    old: String,
+    /// New name of the series
+    new: String,
+}
+
+impl Rename {
+    pub fn run(
+        self,
+        _config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let Self { remote, old, new } = self;
+
+        let old_ref = format!("refs/heads/{old}");
+        let new_ref = format!("refs/heads/{new}");
+        let has_branch = git_cd(&["rev-parse", "--verify", "--quiet", &old_ref]).is_ok();
+        let series = store.series(&old);
+
+        if !has_branch && !series.exists() {
+            return Err(miette!("No branch or series named {old}"));
+        }
+        if git_cd(&["rev-parse", "--verify", "--quiet", &new_ref]).is_ok() {
+            return Err(miette!("A branch named {new} already exists"));
+        }
+        if store.series(&new).exists() {
+            return Err(miette!("A series named {new} already exists"));
+        }
+
+        let upstream = git_cd(&["config", "--get", &format!("branch.{old}.remote")])
+            .ok()
+            .zip(git_cd(&["config", "--get", &format!("branch.{old}.merge")]).ok())
+            .filter(|_| has_branch);
+
+        // Only a remote branch of the same name is the counterpart of the branch, one it merely
+        // tracks such as the default branch is shared with other series
+        let remote_name = match (&upstream, remote) {
+            (_, false) => None,
+            (None, true) => return Err(miette!("The branch {old} has no upstream to rename")),
+            (Some((remote_name, merge)), true) => {
+                if remote_name == "." {
+                    return Err(miette!(
+                        "The branch {old} tracks the local branch {merge}, there is no remote branch to rename"
+                    ));
+                }
+                if merge != &old_ref {
+                    return Err(miette!(
+                        help = "Rename it without --remote, the remote branch is left untouched",
+                        "The branch {old} tracks {remote_name}/{}, which is not its own remote branch",
+                        merge.strip_prefix("refs/heads/").unwrap_or(merge)
+                    ));
+                }
+                Some(remote_name.clone())
+            }
+        };
+
+        if has_branch {
+            // Renaming the branch also moves its configuration, including its upstream
+            git_cd(&["branch", "-m", &old, &new])?;
+            println!("Renamed the branch {old} to {new}");
+
+            match (&remote_name, &upstream) {
+                (Some(remote_name), _) => {
+                    git_cd(&["push", remote_name, &format!("{new_ref}:{new_ref}")])?;
+                    git_cd(&["push", "-d", remote_name, &old_ref])?;
+                    git_cd(&[
+                        "branch",
+                        &format!("--set-upstream-to={remote_name}/{new}"),
+                        &new,
+                    ])?;
+                    println!(
+                        "Renamed the remote branch {remote_name}/{old} to {remote_name}/{new}"
+                    );
+                }
+                (None, Some((tracked, merge))) if merge == &old_ref => {
+                    println!("The branch still tracks {tracked}/{old}, use --remote to rename it")
+                }
+                (None, _) => (),
+            }
+        }
+
+        if series.exists() {
+            let renamed = store.series(&new);
+            series.move_to(&renamed)?;
+            revive_series(&renamed)?;
+            store.bury(&old, RemovalReason::Renamed)?;
+
+            // The stored versions are kept as sent, only the cover letter of the next one changes
+            let cover_letter = renamed.cover_letter_path();
+            if cover_letter.exists() {
+                let content = std::fs::read_to_string(&cover_letter)
+                    .into_diagnostic()
+                    .wrap_err("Could not read the cover letter")?;
+                let content = content
+                    .lines()
+                    .map(|l| match l == format!("Branch: {old}") {
+                        true => format!("Branch: {new}"),
+                        false => l.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    + "\n";
+                std::fs::write(&cover_letter, content)
+                    .into_diagnostic()
+                    .wrap_err("Could not update the cover letter")?;
+            }
+
+            let outbox = outbox::Outbox::new(store.dir());
+            for item in outbox.items()? {
+                if item.series == old {
+                    outbox.save(&OutboxItem {
+                        series: new.clone(),
+                        ..item
+                    })?;
+                }
+            }
+
+            for child in store.all_series()? {
+                let mut metadata = SeriesMetadata::load(child.dir())?;
+                if metadata.parent.as_deref() == Some(old.as_str()) {
+                    metadata.parent = Some(new.clone());
+                    metadata.save(child.dir())?;
+                    println!("Updated the parent of {}", child.name());
+                }
+            }
+
+            println!("Moved the series {old} to {new}");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Rebase {
+    #[arg(
+        long,
+        help = "Reference to rebase onto (defaults to the parent series or ${config.interdiff_base})"
+    )]
+    onto: Option<String>,
+    /// Series to rebase (defaults to the current branch)
+    series: Option<String>,
+}
+
+impl Rebase {
+    /// Rebase the branch `name` from `from` onto `onto`, recording it for the next version
+    fn rebase_series(
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+        name: &str,
+        reference: &str,
+        from: &str,
+        onto: &str,
+    ) -> Result<()> {
+        if from == onto {
+            println!("{name} is already based on {reference}");
+            return Ok(());
+        }
+
+        if let Err(e) = git_cd(&["rebase", "--onto", onto, from, name]) {
+            let _ = git_cd(&["rebase", "--abort"]);
+            return Err(e.wrap_err(format!(
+                "Could not rebase {name} onto {reference}, rebase it by hand with `git rebase --onto {onto} {from} {name}`"
+            )));
+        }
+        println!("Rebased {name} onto {reference}");
+
+        let series = store.series(name);
+        if series.exists() {
+            let mut metadata = SeriesMetadata::load(series.dir())?;
+            metadata.rebases.push(RebaseRecord {
+                version: series.latest()?.map(|v| v.number + 1).unwrap_or(1),
+                date: chrono::Local::now().fixed_offset(),
+                reference: reference.to_string(),
+                from: from.to_string(),
+                onto: onto.to_string(),
+            });
+            metadata.save(series.dir())?;
+        }
+
+        Ok(())
+    }
+
+    /// Series stacked directly on `parent`
+    fn children(store: &PatchStore, parent: &str) -> Result<Vec<String>> {
+        let mut children = Vec::new();
+        for series in store.all_series()? {
+            if SeriesMetadata::load(series.dir())?.parent.as_deref() == Some(parent) {
+                children.push(series.name().to_string());
+            }
+        }
+
+        Ok(children)
+    }
+
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let current_branch = git_cd(&["branch", "--show-current"])?;
+        let name = self
+            .series
+            .as_ref()
+            .try_m_unwrap_or_else(|| Ok(&current_branch))?;
+
+        let branch_ref = format!("refs/heads/{name}");
+        let old_tip = git_cd(&["rev-parse", "--verify", &branch_ref])
+            .wrap_err_with(|| format!("No branch named {name}"))?;
+
+        let series = store.series(name);
+        let metadata = SeriesMetadata::load(series.dir())?;
+
+        let reference = match (&self.onto, &metadata.parent) {
+            (Some(onto), _) => onto.clone(),
+            (None, Some(parent)) => SeriesMetadata::load(store.series(parent).dir())?.tip(parent),
+            (None, None) => {
+                let upstream = config.upstream(&git_cd, name);
+                fetch_upstream(&git_cd, &upstream)?;
+                upstream
+            }
+        };
+        let onto = git_cd(&["rev-parse", "--verify", &format!("{reference}^{{commit}}")])
+            .wrap_err_with(|| format!("Could not find {reference}"))?;
+
+        // A stacked series starts at the tip of its parent when it was formatted, while others
+        // start where they fork from their base
+        let recorded_base = match series.latest()? {
+            Some(latest) if metadata.parent.is_some() => {
+                version_base(&git_cd, &series, latest.number)?
+                    .filter(|base| git_cd(&["merge-base", "--is-ancestor", base, &old_tip]).is_ok())
+            }
+            _ => None,
+        };
+        let from = match recorded_base {
+            Some(base) => base,
+            None => git_cd(&["merge-base", &onto, &old_tip])?,
+        };
+
+        Self::rebase_series(&git_cd, store, name, &reference, &from, &onto)?;
+
+        // Children are moved from the old tip of their parent to the new one
+        let mut stack = vec![(name.clone(), old_tip)];
+        while let Some((parent, parent_old_tip)) = stack.pop() {
+            let parent_tip = git_cd(&["rev-parse", &format!("refs/heads/{parent}")])?;
+            for child in Self::children(store, &parent)? {
+                let Ok(child_tip) =
+                    git_cd(&["rev-parse", "--verify", &format!("refs/heads/{child}")])
+                else {
+                    println!("Skipping {child}: its branch does not exist");
+                    continue;
+                };
+
+                Self::rebase_series(
+                    &git_cd,
+                    store,
+                    &child,
+                    &parent,
+                    &parent_old_tip,
+                    &parent_tip,
+                )?;
+                stack.push((child, child_tip));
+            }
+        }
+
+        if !current_branch.is_empty() && git_cd(&["branch", "--show-current"])? != current_branch {
+            git_cd(&["switch", &current_branch])?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Checkout {
+    series: String,
+    #[arg(value_parser = parse_version)]
+    version: u64,
+    #[arg(
+        short,
+        long,
+        help = "Branch to create (defaults to the name of the series)"
+    )]
+    branch: Option<String>,
+    #[arg(
+        short = 'B',
+        long,
+        help = "Commit to apply the patches on (defaults to the recorded base of the version or ${config.interdiff_base})"
+    )]
+    base: Option<String>,
+    #[arg(short, long, help = "Reset the branch if it already exists")]
+    force: bool,
+}
+
+impl Checkout {
+    pub fn run(
+        self,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
+    ) -> Result<()> {
+        let series = store.existing_series(&self.series)?;
+        let patches = series.existing_version(self.version)?.patches()?;
+        let branch = self.branch.unwrap_or_else(|| self.series.clone());
+
+        let branch_exists = git_cd(&[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/heads/{branch}"),
+        ])
+        .is_ok();
+        if branch_exists && !self.force {
+            return Err(miette!(
+                "The branch {branch} exists, pass --force to reset it or --branch to use another name"
+            ));
+        }
+        if branch_exists && git_cd(&["branch", "--show-current"])? == branch {
+            return Err(miette!(
+                "The branch {branch} is checked out, switch to another branch first"
+            ));
+        }
+
+        let base = match (self.base, version_base(&git_cd, &series, self.version)?) {
+            (Some(base), _) => base,
+            (None, Some(base)) => base,
+            (None, None) => config.upstream(&git_cd, series.name()),
+        };
+
+        let tip = worktree::apply_patches(&git_cd, &base, &patches)
+            .wrap_err_with(|| format!("Could not rebuild v{}", self.version))?;
+
+        let mut branch_args = vec!["branch"];
+        if self.force {
+            branch_args.push("--force");
+        }
+        branch_args.extend([branch.as_str(), tip.as_str()]);
+        git_cd(&branch_args)?;
+
+        println!(
+            "Created the branch {branch} at {} from v{} of {}",
+            &tip[..12],
+            self.version,
+            series.name()
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct List {
+    #[arg(short, long, help = "Also list the patches of the latest version")]
+    verbose: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    #[arg(long, value_enum, default_value_t = ListSort::Name)]
+    sort: ListSort,
+    #[arg(short, long, help = "Reverse the sorting order")]
+    reverse: bool,
+    #[arg(long, value_enum, help = "Only list the series in this sending state")]
+    state: Option<SentState>,
+    #[arg(
+        long,
+        help = "Only list the series with commits since their latest version"
+    )]
+    changed: bool,
+    #[arg(
+        long,
+        help = "Also count the patches of the latest version applied upstream"
+    )]
+    applied: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ListSort {
+    Name,
+    /// Date of the latest version
+    Date,
+    /// Number of versions
+    Versions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum SentState {
+    Unsent,
+    Partial,
+    Sent,
+}
+
+impl std::fmt::Display for SentState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SentState::Unsent => write!(f, "unsent"),
+            SentState::Partial => write!(f, "partial"),
+            SentState::Sent => write!(f, "sent"),
         }
+    }
+}
 
-        if branch == &current_branch {
-            println!("Branch {branch} currently checked out, switching to master");
-            git_cd(&[
-                "switch",
-                config.interdiff_base.as_deref().unwrap_or("master"),
-            ])?;
-        }
+#[derive(Debug, serde::Serialize)]
+struct SeriesSummary {
+    series: String,
+    versions: usize,
+    latest: u64,
+    formatted: Option<DateTime<FixedOffset>>,
+    sent: SentState,
+    /// Commits on the branch since the latest version, when it can be known
+    new_commits: Option<u64>,
+    branch_exists: bool,
+    /// Number of patches of the latest version, without the cover letter
+    patch_count: usize,
+    /// Patches of the latest version already applied upstream, when asked for and its base is
+    /// known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    applied: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patches: Option<Vec<String>>,
+}
 
-        let branch_delete = match self.force {
-            true => "-D",
-            false => "-d",
+impl SeriesSummary {
+    fn new(
+        series: &Series,
+        verbose: bool,
+        upstream: Option<&str>,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+    ) -> Result<Option<Self>> {
+        let versions = series.versions().wrap_err("Could not fetch versions")?;
+        let Some(latest) = versions.last() else {
+            return Ok(None);
         };
 
-        git_cd(&["branch", branch_delete, branch])?;
-        let branch_dir = patch_dir.join(&branch);
-        std::fs::remove_dir_all(branch_dir).into_diagnostic()?;
+        let metadata = SeriesMetadata::load(series.dir())?;
+        let latest_metadata = metadata.version(latest.number);
+        let patches = latest.patches()?;
+
+        let sent = match latest_metadata {
+            None => 0,
+            Some(v) => patches
+                .iter()
+                .filter_map(|p| p.file_name())
+                .filter(|p| v.sent(&p.to_string_lossy()).is_some())
+                .count(),
+        };
+        let sent = match sent {
+            0 => SentState::Unsent,
+            n if n == patches.len() => SentState::Sent,
+            _ => SentState::Partial,
+        };
 
-        Ok(())
-    }
-}
+        let formatted = match latest_metadata.and_then(|v| v.formatted) {
+            Some(formatted) => Some(formatted),
+            None => std::fs::metadata(latest.dir())
+                .and_then(|m| m.modified())
+                .ok()
+                .map(|m| DateTime::<chrono::Local>::from(m).fixed_offset()),
+        };
 
-#[derive(Args, Debug)]
-struct List {
-    #[arg(short, long)]
-    verbose: bool,
+        let tip = metadata.tip(series.name());
+        let branch_exists = git_cd(&["rev-parse", "--verify", "--quiet", &tip]).is_ok();
+        let new_commits = match (branch_exists, latest_metadata.and_then(|v| v.head.as_ref())) {
+            (true, Some(head)) => git_cd(&["rev-list", "--count", &format!("{head}..{tip}")])
+                .ok()
+                .and_then(|c| c.parse().ok()),
+            _ => None,
+        };
+
+        let patch_count = patches
+            .iter()
+            .filter(|p| !p.to_string_lossy().ends_with("cover-letter.patch"))
+            .count();
+        // Matching the patches against upstream is slow, so only done on request
+        let applied = match upstream {
+            Some(upstream) => applied_patches(&git_cd, series, latest.number, upstream)?,
+            None => None,
+        };
+
+        Ok(Some(Self {
+            series: series.name().to_string(),
+            versions: versions.len(),
+            latest: latest.number,
+            formatted,
+            sent,
+            new_commits,
+            branch_exists,
+            patch_count,
+            applied,
+            patches: verbose.then(|| {
+                patches
+                    .iter()
+                    .filter_map(|p| p.file_name())
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .collect()
+            }),
+        }))
+    }
 }
 
 impl List {
     pub fn run(
         self,
-        _config: GsmConfig,
-        _git_cd: impl Fn(&[&str]) -> Result<String>,
-        patch_dir: &Path,
+        config: GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        store: &PatchStore,
     ) -> Result<()> {
-        for entry in patch_dir
-            .read_dir()
-            .into_diagnostic()
-            .wrap_err("Could not read patch dir")?
-        {
-            let entry = entry
-                .into_diagnostic()
-                .wrap_err("Could not read patch dir entry")?;
+        let mut series = Vec::new();
+        for stored in store.all_series()? {
+            let upstream = self
+                .applied
+                .then(|| config.upstream(&git_cd, stored.name()));
+            let Some(summary) =
+                SeriesSummary::new(&stored, self.verbose, upstream.as_deref(), &git_cd)?
+            else {
+                continue;
+            };
 
-            if entry.file_name() == "config.toml" {
+            if self.state.is_some_and(|s| s != summary.sent)
+                || (self.changed && summary.new_commits.unwrap_or(0) == 0)
+            {
                 continue;
             }
 
-            let branch_dir = patch_dir.join(entry.file_name());
-            let Some(branch_version) =
-                latest_version(&branch_dir).wrap_err("Could not fetch latest version")?
-            else {
-                continue;
-            };
+            series.push(summary);
+        }
 
-            println!(
-                " - {}: v{branch_version}",
-                entry.file_name().to_string_lossy()
-            );
+        match self.sort {
+            ListSort::Name => series.sort_by(|a, b| a.series.cmp(&b.series)),
+            ListSort::Date => series.sort_by_key(|s| s.formatted),
+            ListSort::Versions => series.sort_by_key(|s| s.versions),
+        }
+        if self.reverse {
+            series.reverse();
+        }
 
-            if self.verbose {
-                println!("   Patches:");
-                for entry in branch_dir
-                    .join(branch_version.to_string())
-                    .read_dir()
+        match self.format {
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&series)
                     .into_diagnostic()
-                    .wrap_err("Could not read patchset dir")?
-                {
-                    let entry = entry
-                        .into_diagnostic()
-                        .wrap_err("Could not read patchset entry")?;
+                    .wrap_err("Could not serialize series list")?
+            ),
+            OutputFormat::Text => {
+                let mut header = vec![
+                    "SERIES".to_string(),
+                    "VERSIONS".to_string(),
+                    "LATEST".to_string(),
+                    "FORMATTED".to_string(),
+                    "SENT".to_string(),
+                    "NEW COMMITS".to_string(),
+                    "BRANCH".to_string(),
+                ];
+                if self.applied {
+                    header.insert(6, "APPLIED".to_string());
+                }
+                let mut rows = vec![header];
+                rows.extend(series.iter().map(|s| {
+                    let mut row = vec![
+                        s.series.clone(),
+                        s.versions.to_string(),
+                        format!("v{}", s.latest),
+                        s.formatted
+                            .map(|f| f.format("%Y-%m-%d %H:%M").to_string())
+                            .unwrap_or_else(|| "-".into()),
+                        s.sent.to_string(),
+                        s.new_commits
+                            .map(|c| c.to_string())
+                            .unwrap_or_else(|| "?".into()),
+                        match s.branch_exists {
+                            true => "yes".into(),
+                            false => "deleted".into(),
+                        },
+                    ];
+                    if self.applied {
+                        row.insert(
+                            6,
+                            match &s.applied {
+                                Some(applied) => format!("{}/{}", applied.len(), s.patch_count),
+                                None => "?".into(),
+                            },
+                        );
+                    }
+                    row
+                }));
+
+                let widths: Vec<_> = (0..rows[0].len())
+                    .map(|i| rows.iter().map(|r| r[i].len()).max().unwrap_or(0))
+                    .collect();
 
-                    println!("    - {}", entry.file_name().to_string_lossy());
+                for (row, summary) in rows
+                    .iter()
+                    .zip(std::iter::once(None).chain(series.iter().map(Some)))
+                {
+                    let line = row
+                        .iter()
+                        .zip(&widths)
+                        .map(|(cell, width)| format!("{cell:width$}"))
+                        .collect::<Vec<_>>()
+                        .join("  ");
+                    println!("{}", line.trim_end());
+
+                    if let Some(patches) = summary.and_then(|s| s.patches.as_ref()) {
+                        for patch in patches {
+                            println!("    - {patch}");
+                        }
+                    }
                 }
             }
         }
@@ -163,6 +2242,53 @@ struct Send {
         help = "Version of the patchset to set. Defaults to the latest version"
     )]
     version: Option<u64>,
+    #[arg(
+        long,
+        help = "Only send the emails that were not sent by a previous attempt"
+    )]
+    resume: bool,
+    #[arg(
+        long,
+        help = "Send the patchset again even if it was already sent",
+        conflicts_with = "resume"
+    )]
+    force: bool,
+    #[arg(
+        long,
+        help = "Seconds to wait between each email (defaults to ${config.throttle})"
+    )]
+    throttle: Option<u64>,
+    #[arg(
+        short = 'n',
+        long,
+        help = "Show the emails and their recipients without sending them"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        overrides_with = "no_cc_reviewers",
+        help = "Cc the reviewers and participants of the previous versions (defaults to ${config.reviewers.cc})"
+    )]
+    cc_reviewers: bool,
+    #[arg(long, help = "Do not Cc the reviewers of the previous versions")]
+    no_cc_reviewers: bool,
+    #[arg(long, help = "Additional recipient, or alias of recipients")]
+    to: Vec<String>,
+    #[arg(long, help = "Additional Cc, or alias of recipients")]
+    cc: Vec<String>,
+    #[arg(long, help = "Queue the patchset in the outbox instead of sending it")]
+    queue: bool,
+    #[arg(
+        long,
+        requires = "queue",
+        value_parser = outbox::parse_time,
+        help = "Do not send the queued patchset before this time"
+    )]
+    at: Option<DateTime<FixedOffset>>,
+    #[arg(long, help = "Transport to use, overriding the configuration")]
+    transport: Option<TransportKind>,
+    #[arg(long, help = "Destination of the file, mbox and maildir transports")]
+    sink: Option<PathBuf>,
     #[arg(help = "Patch series to send. Defaults to the current branch")]
     series: Option<String>,
 }
@@ -170,9 +2296,9 @@ struct Send {
 impl Send {
     pub fn run(
         self,
-        config: GsmConfig,
+        mut config: GsmConfig,
         git_cd: impl Fn(&[&str]) -> Result<String>,
-        patch_dir: &Path,
+        store: &PatchStore,
     ) -> Result<()> {
         let current_branch = git_cd(&["branch", "--show-current"])?;
         let branch = self
@@ -180,34 +2306,234 @@ impl Send {
             .as_ref()
             .try_m_unwrap_or_else(|| Ok(&current_branch))?;
 
-        let branch_dir = patch_dir.join(&branch);
-        let version = match self.version {
-            Some(v) => v,
-            None => match latest_version(&branch_dir)? {
-                None => return Err(miette!("No patch set for the branch {branch}")),
-                Some(v) => v,
-            },
+        let series = store.series(branch);
+        let stored_version = match self.version {
+            Some(v) => series.existing_version(v)?,
+            None => series
+                .latest()?
+                .ok_or(miette!("No patch set for the branch {branch}"))?,
         };
+        let version = stored_version.number;
+
+        if self.queue {
+            let id = outbox::Outbox::new(store.dir()).queue(OutboxItem {
+                id: 0,
+                series: branch.clone(),
+                version,
+                queued: chrono::Local::now().fixed_offset(),
+                at: self.at,
+                transport: self.transport,
+                sink: self.sink,
+                throttle: self.throttle,
+                to: self.to,
+                cc: self.cc,
+            })?;
+            println!("Queued {branch} v{version} in the outbox as {id}");
+            return Ok(());
+        }
+
+        if let Some(transport) = self.transport {
+            config.transport = transport;
+        }
+        if let Some(sink) = self.sink {
+            config.sink = Some(sink);
+        }
+
+        let mut metadata = SeriesMetadata::load(series.dir())?;
+        let cover_letter = CoverLetter::read(&series)?;
+        let cover_letter = cover_letter
+            .as_deref()
+            .map(CoverLetter::parse)
+            .transpose()?;
+
+        let sender = mail::sender(config.from.as_deref(), &git_cd)?;
+        let mut recipients = Vec::new();
+        for (addresses, field, reason) in [
+            (&config.to, Field::To, "configured in `to`"),
+            (&config.cc, Field::Cc, "configured in `cc`"),
+            (&metadata.to, Field::To, "listed in the series metadata"),
+            (&metadata.cc, Field::Cc, "listed in the series metadata"),
+            (&self.to, Field::To, "given on the command line"),
+            (&self.cc, Field::Cc, "given on the command line"),
+        ] {
+            for address in addresses {
+                recipients.extend(
+                    config
+                        .aliases
+                        .expand(Recipient::new(address, field, reason))?,
+                );
+            }
+        }
+        if let Some(cover_letter) = &cover_letter {
+            for (addresses, field) in [(&cover_letter.to, Field::To), (&cover_letter.cc, Field::Cc)]
+            {
+                for address in addresses {
+                    recipients.extend(config.aliases.expand(Recipient::new(
+                        address,
+                        field,
+                        "listed in the cover letter",
+                    ))?);
+                }
+            }
+        }
+
+        let mut emails = mail::prepare_series(&stored_version.patches()?, &sender, &recipients)?;
+
+        if let Some(maintainers) = &config.maintainers {
+            let repo_root = PathBuf::from(git_cd(&["rev-parse", "--show-toplevel"])?);
+            let maintainers = Maintainers::load(maintainers, &repo_root)?;
+
+            let mut series_recipients = Vec::new();
+            for email in emails.iter_mut().filter(|e| !e.is_cover_letter()) {
+                for recipient in maintainers.recipients(&email.files) {
+                    let recipients = config.aliases.expand(recipient)?;
+                    for recipient in recipients {
+                        email.add_recipient(&recipient);
+                        series_recipients.push(Recipient {
+                            reason: format!("{} in {}", recipient.reason, email.file_name),
+                            ..recipient
+                        });
+                    }
+                }
+            }
+
+            for email in emails.iter_mut().filter(|e| e.is_cover_letter()) {
+                for recipient in &series_recipients {
+                    email.add_recipient(recipient);
+                }
+            }
+        }
 
-        let version_dir = &branch_dir.join(&version.to_string());
+        let cc_reviewers = match (self.cc_reviewers, self.no_cc_reviewers) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => config.reviewers.cc,
+        };
+        if cc_reviewers {
+            let sender = mail::address_of(&sender).to_lowercase();
+            let previous = metadata
+                .versions
+                .iter()
+                .filter(|v| v.version < version)
+                .flat_map(|v| {
+                    let reviewers = v
+                        .reviewers
+                        .iter()
+                        .map(move |r| (r, format!("reviewed v{}", v.version)));
+                    let participants = v
+                        .participants
+                        .iter()
+                        .map(move |p| (p, format!("took part in the v{} discussion", v.version)));
+                    reviewers.chain(participants)
+                })
+                .filter(|(address, _)| {
+                    mail::address_of(address).to_lowercase() != sender
+                        && !config.reviewers.is_ignored(address)
+                })
+                .map(|(address, reason)| Recipient::new(address, Field::Cc, reason))
+                .collect::<Vec<_>>();
 
-        let mut cmd = std::process::Command::new("git");
+            for email in &mut emails {
+                for recipient in &previous {
+                    email.add_recipient(recipient);
+                }
+            }
+        }
 
-        cmd.arg("send-email");
-        if let Some(args) = &config.sendmail_args {
-            cmd.args(args.iter());
+        let invalid = std::iter::once(&sender)
+            .chain(emails.iter().flat_map(|e| e.to.iter().chain(&e.cc)))
+            .filter_map(|address| mail::validate_address(address).err())
+            .map(|e| e.to_string())
+            .collect::<BTreeSet<_>>();
+        if !invalid.is_empty() {
+            return Err(miette!(
+                "Invalid addresses:\n{}",
+                invalid
+                    .iter()
+                    .map(|e| format!(" - {e}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ));
         }
-        cmd.arg(version_dir);
 
-        let status = cmd
-            .status()
-            .into_diagnostic()
-            .wrap_err("Could not send emails")?;
+        let version_metadata = metadata.version_mut(version);
+
+        let already_sent = emails
+            .iter()
+            .filter(|e| version_metadata.sent(&e.file_name).is_some())
+            .count();
+        if already_sent != 0 && !self.resume && !self.force && !self.dry_run {
+            return Err(miette!(
+                help = "pass --resume to send the remaining emails, or --force to send everything again",
+                "{already_sent} emails of {branch} v{version} were already sent"
+            ));
+        }
 
-        if !status.success() {
-            return Err(miette!("Could not send emails"));
+        if self.force {
+            version_metadata.emails.clear();
         }
 
+        if let Some(root) = emails
+            .first()
+            .and_then(|e| version_metadata.sent(&e.file_name))
+        {
+            let root = root.message_id.clone();
+            for email in &mut emails[1..] {
+                email.reply_to(&root);
+            }
+        }
+        emails.retain(|e| version_metadata.sent(&e.file_name).is_none());
+
+        if emails.is_empty() {
+            println!("All emails of {branch} v{version} were already sent");
+            return Ok(());
+        }
+
+        if self.dry_run {
+            for email in &emails {
+                println!("{}: {}", email.file_name, email.subject);
+                for (field, addresses) in [("To", &email.to), ("Cc", &email.cc)] {
+                    for address in addresses {
+                        println!("    {field}: {address}");
+                        for reason in email.reasons_for(address) {
+                            println!("        - {reason}");
+                        }
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+
+        let throttle = self
+            .throttle
+            .or(config.throttle)
+            .map(std::time::Duration::from_secs);
+
+        let mut transport = transport::from_config(&config)?;
+        transport
+            .send_all(&emails, throttle, &mut |email, result| {
+                let version_metadata = metadata.version_mut(version);
+                if result.is_ok() {
+                    for (_, reviewer) in email.review_trailers() {
+                        metadata::add_address(&mut version_metadata.reviewers, &reviewer);
+                    }
+                }
+                version_metadata.record(EmailRecord {
+                    file: email.file_name.clone(),
+                    message_id: email.message_id.clone(),
+                    date: email.date.clone(),
+                    error: result.as_ref().err().map(|e| {
+                        e.chain()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(": ")
+                    }),
+                });
+                metadata.save(series.dir())
+            })
+            .wrap_err("Sending was interrupted, use --resume to send the remaining emails")?;
+
         Ok(())
     }
 }
@@ -245,15 +2571,105 @@ struct FormatPatch {
         conflicts_with = "diff"
     )]
     diff_to: Option<String>,
+    #[arg(long, help = "Drop the commits applied upstream without asking")]
+    drop_applied: bool,
+    #[arg(long, help = "Name of the series (defaults to the branch)")]
+    series: Option<String>,
+    #[arg(
+        long,
+        help = "Commits of the series as base..tip, remembered for its next versions"
+    )]
+    range: Option<String>,
+    #[arg(
+        long,
+        help = "Series this one builds on, remembered for its next versions"
+    )]
+    parent: Option<String>,
+    #[arg(
+        long,
+        help = "Forget the series this one was built on, once it was applied or dropped",
+        conflicts_with = "parent"
+    )]
+    no_parent: bool,
     extra_args: Vec<String>,
 }
 
 impl FormatPatch {
+    /// Offer to drop the commits of the branch already applied upstream, returning their subjects
+    fn drop_applied(
+        &self,
+        config: &GsmConfig,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        branch: &str,
+    ) -> Result<Vec<String>> {
+        let upstream = config.upstream(&git_cd, branch);
+        let Ok(fork_point) = git_cd(&["merge-base", &upstream, branch]) else {
+            return Ok(Vec::new());
+        };
+
+        let merged = Upstream::load(&git_cd, &format!("{fork_point}..{upstream}"))?;
+        let applied: Vec<_> = upstream::of_range(&git_cd, &format!("{fork_point}..{branch}"))?
+            .into_iter()
+            .filter(|c| merged.contains(c))
+            .collect();
+        if applied.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        println!("{} commits were applied in {upstream}:", applied.len());
+        for change in &applied {
+            println!("  - {}", change.subject);
+        }
+
+        if !git_cd(&["rev-list", "--merges", &format!("{fork_point}..{branch}")])?.is_empty() {
+            println!("{branch} has merge commits, drop them yourself");
+            return Ok(Vec::new());
+        }
+        if !self.drop_applied && !confirm("Drop them from the branch?")? {
+            return Ok(Vec::new());
+        }
+
+        // The kept commits are replayed on the fork point away from the checkout, which is only
+        // moved once the result is checked
+        let kept: Vec<_> = git_cd(&["rev-list", "--reverse", &format!("{fork_point}..{branch}")])?
+            .lines()
+            .filter(|commit| !applied.iter().any(|c| c.commit == *commit))
+            .map(str::to_string)
+            .collect();
+
+        let wt = worktree::Worktree::new(&git_cd, &fork_point)?;
+        if !kept.is_empty() {
+            let mut cherry_pick = vec!["cherry-pick", "--allow-empty", "--keep-redundant-commits"];
+            cherry_pick.extend(kept.iter().map(|s| s.deref()));
+            if let Err(e) = wt.exec(&cherry_pick) {
+                let _ = wt.exec(&["cherry-pick", "--abort"]);
+                return Err(e.wrap_err("Could not drop the applied commits"));
+            }
+        }
+        let rebuilt = wt.exec(&["rev-parse", "HEAD"])?;
+
+        let remaining = upstream::of_range(&git_cd, &format!("{fork_point}..{rebuilt}"))?;
+        if remaining.len() != kept.len() || remaining.iter().any(|c| merged.contains(c)) {
+            return Err(miette!(
+                help = format!("{branch} was left untouched, the rebuilt branch is {rebuilt}"),
+                "Could not drop the applied commits"
+            ));
+        }
+
+        match git_cd(&["branch", "--show-current"])? == branch {
+            true => git_cd(&["reset", "--keep", &rebuilt])?,
+            false => git_cd(&["branch", "-f", branch, &rebuilt])?,
+        };
+        println!("Dropped {} commits from {branch}", applied.len());
+
+        Ok(applied.into_iter().map(|c| c.subject).collect())
+    }
+
     pub fn run(
         self,
         config: GsmConfig,
         git_cd: impl Fn(&[&str]) -> Result<String>,
-        patch_dir: &Path,
+        store: &PatchStore,
     ) -> Result<()> {
         if config.ci_url.is_some() && self.ci.is_none() {
             eprintln!("WARNING: CI was not specified\n");
@@ -261,10 +2677,11 @@ impl FormatPatch {
 
         let branch = self
             .branch
-            .try_m_unwrap_or_else(|| Ok(git_cd(&["branch", "--show-current"])?))?;
+            .clone()
+            .try_m_unwrap_or_else(|| git_cd(&["branch", "--show-current"]))?;
 
-        let component = config.component.try_m_unwrap_or_else(|| {
-            let url = git_cd(&["remote", "get-url", "origin"])?;
+        let component = config.component.clone().try_m_unwrap_or_else(|| {
+            let url = git_cd(&["remote", "get-url", &config.remote(&git_cd, &branch)])?;
             Ok(url
                 .strip_prefix(&config.repo_url_base)
                 .ok_or(miette!(
@@ -278,7 +2695,7 @@ impl FormatPatch {
         println!("Component: {component}");
         println!("Branch: {branch}");
 
-        let ci_link = match (config.ci_url, self.ci) {
+        let ci_link = match (&config.ci_url, self.ci) {
             (Some(ci_template), Some(id)) => Some(
                 ci_template
                     .replace("${component}", &component)
@@ -293,28 +2710,119 @@ impl FormatPatch {
             (None, _) => None,
         };
 
-        let branch_dir = patch_dir.join(&branch);
-        std::fs::create_dir_all(&branch_dir)
-            .into_diagnostic()
-            .wrap_err("could not create branch dir")?;
+        let name = self.series.clone().unwrap_or_else(|| branch.clone());
+        let series = store.series(&name);
+        if name != branch {
+            println!("Series: {name}");
+        }
+
+        let stored_metadata = SeriesMetadata::load(series.dir())?;
+        let range = self.range.clone().or(stored_metadata.range);
+        if let Some(range) = &range {
+            let Some((base, tip)) = range.split_once("..") else {
+                return Err(miette!("The range {range} is not of the form base..tip"));
+            };
+            for commit in [base, tip].into_iter().filter(|c| !c.is_empty()) {
+                git_cd(&["rev-parse", "--verify", &format!("{commit}^{{commit}}")])
+                    .wrap_err_with(|| format!("Invalid range {range}"))?;
+            }
+            println!("Range: {range}");
+        }
+
+        struct ParentSeries {
+            name: String,
+            version: u64,
+            tip: String,
+            message_id: Option<String>,
+        }
+
+        let stored_parent = stored_metadata.parent.filter(|_| !self.no_parent);
+        let parent = match self.parent.clone().or(stored_parent) {
+            None => None,
+            Some(name) => {
+                let parent_series = store.existing_series(&name).map_err(|e| {
+                    miette!(
+                        help = "Pass --no-parent if it was applied or dropped, or --parent to \
+                                build on another series",
+                        "The parent series {name} is gone: {e}"
+                    )
+                })?;
+                let version = parent_series
+                    .latest()?
+                    .ok_or(miette!("The parent series {name} has no version"))?
+                    .number;
+
+                let parent_metadata = SeriesMetadata::load(parent_series.dir())?;
+                let tip = git_cd(&[
+                    "rev-parse",
+                    "--verify",
+                    &format!("{}^{{commit}}", parent_metadata.tip(&name)),
+                ])
+                .wrap_err_with(|| format!("Could not find the tip of the parent series {name}"))?;
+                let message_id = parent_metadata
+                    .version(version)
+                    .and_then(|v| {
+                        v.emails
+                            .iter()
+                            .find(|e| e.is_sent() && e.file.ends_with("cover-letter.patch"))
+                    })
+                    .map(|e| e.message_id.clone());
+
+                println!("Parent: {name} v{version}");
+                Some(ParentSeries {
+                    name,
+                    version,
+                    tip,
+                    message_id,
+                })
+            }
+        };
+
+        // A stacked series only holds the commits on top of its parent, which become
+        // prerequisites of the base
+        let revisions = match (&range, &parent) {
+            (Some(range), _) => Some(range.clone()),
+            (None, Some(parent)) => Some(format!("{}..{branch}", parent.tip)),
+            (None, None) => None,
+        };
+        let base_arg = match &parent {
+            Some(parent) => git_cd(&[
+                "merge-base",
+                &config.upstream(&git_cd, &parent.name),
+                &parent.tip,
+            ])
+            .ok()
+            .map(|base| format!("--base={base}")),
+            None => None,
+        };
+
+        series.create()?;
 
         let version = match self.version {
             Some(v) => Some(v),
-            None => latest_version(&branch_dir)
+            None => series
+                .latest()
                 .wrap_err("could not get version")?
-                .map(|v| v + 1),
+                .map(|v| v.number + 1),
         };
 
-        let version_dir = branch_dir.join(version.unwrap_or(1).to_string());
+        let stored_version = series.version(version.unwrap_or(1));
+        let version_dir = stored_version.dir();
+
+        if version_dir.exists() && !self.force {
+            return Err(miette!(
+                "Patch dir {version_dir:?} exists, pass --force to delete it"
+            ));
+        }
+
+        // The branch is only rewritten when it holds the whole series
+        let dropped = match revisions {
+            Some(_) => Vec::new(),
+            None => self.drop_applied(&config, &git_cd, &branch)?,
+        };
 
         if version_dir.exists() {
-            if !self.force {
-                return Err(miette!(
-                    "Patch dir {version_dir:?} exists, pass --force to delete it"
-                ));
-            } else {
-                std::fs::remove_dir_all(&version_dir).into_diagnostic()?;
-            }
+            std::fs::remove_dir_all(version_dir).into_diagnostic()?;
         }
 
         let version_dir = version_dir
@@ -343,6 +2851,12 @@ impl FormatPatch {
             let subject_prefix = format!(r#"--subject-prefix=PATCH {component}"#);
             format_patch_args.extend_from_slice(&[&subject_prefix, "--cover-letter"]);
             format_patch_args.extend_from_slice(extra_args);
+            if let Some(base_arg) = &base_arg {
+                format_patch_args.push(base_arg);
+            }
+            if let Some(revisions) = &revisions {
+                format_patch_args.push(revisions);
+            }
             format_patch_args.extend(self.extra_args.iter().map(|s| s.deref()));
 
             git_cd(&format_patch_args)?;
@@ -356,106 +2870,25 @@ impl FormatPatch {
             (None, None) => format_patch(&[])?,
             (Some(_), Some(_)) => unreachable!(),
             (Some(patch_version), None) => {
-                let base = self
-                    .base_diff
-                    .or(config.interdiff_base)
-                    .unwrap_or_else(|| String::from("origin/master"));
-
-                struct TempBranch<'a> {
-                    name: &'a str,
-                    git: &'a dyn Fn(&[&str]) -> Result<String>,
-                }
-
-                impl<'a> Drop for TempBranch<'a> {
-                    fn drop(&mut self) {
-                        (self.git)(&["branch", "-D", self.name]).unwrap();
-                    }
-                }
-
-                let branch = {
-                    let name = "__patch_old";
-                    git_cd(&["branch", name, &base])?;
-                    TempBranch { name, git: &git_cd }
-                };
-
-                struct GitWorktree {
-                    _dir: TempDir,
-                    path: String,
-                }
-
-                impl GitWorktree {
-                    pub fn exec(&self, args: &[&str]) -> Result<String> {
-                        let mut a = vec!["-C", &self.path];
-                        a.extend_from_slice(args);
-
-                        git_bare(a)
-                    }
-                }
-
-                impl Drop for GitWorktree {
-                    fn drop(&mut self) {
-                        self.exec(&["worktree", "remove", "--force", &self.path])
-                            .unwrap();
-                    }
-                }
-
-                let wt = {
-                    let worktree = temp_dir::TempDir::new()
-                        .into_diagnostic()
-                        .wrap_err("Could not create worktree directory")?;
-
-                    let worktree_path = worktree
-                        .path()
-                        .to_str()
-                        .ok_or(miette!("Temp dir is not utf-8"))?
-                        .to_string();
-
-                    git_cd(&["worktree", "add", "--detach", &worktree_path])?;
-
-                    GitWorktree {
-                        path: worktree_path,
-                        _dir: worktree,
-                    }
+                let base = match (self.base_diff, &parent) {
+                    (Some(base), _) => base,
+                    (None, Some(parent)) => version_base(&git_cd, &series, patch_version)?
+                        .unwrap_or_else(|| parent.tip.clone()),
+                    (None, None) => config.upstream(&git_cd, &branch),
                 };
 
-                wt.exec(&["switch", branch.name])?;
-
-                let patches = branch_dir
-                    .join(&patch_version.to_string())
-                    .read_dir()
-                    .into_diagnostic()
-                    .wrap_err("Could not read interdiff folder")?
-                    .map(|e| -> Result<_> {
-                        let e = e
-                            .into_diagnostic()
-                            .wrap_err("Could not read interdiff entry")?;
-
-                        let path = e
-                            .path()
-                            .to_str()
-                            .ok_or(miette!("Interdiff patch path is not utf-8"))?
-                            .to_string();
-
-                        match path.contains("cover-letter") {
-                            true => Ok(None),
-                            false => Ok(Some(path)),
-                        }
-                    })
-                    .filter_map(|e| e.transpose())
-                    .collect::<Result<Vec<_>>>()?;
-                let mut apply_args = vec!["am", "-3"];
-                apply_args.extend(patches.iter().map(|s| s.deref()));
-                wt.exec(&apply_args)?;
+                let patches = series
+                    .existing_version(patch_version)?
+                    .patches()
+                    .wrap_err("Could not read interdiff folder")?;
+                let old = worktree::apply_patches(&git_cd, &base, &patches)?;
 
-                let interdiff_branch = format!("--interdiff={}", branch.name);
-                format_patch(&[&interdiff_branch])?
+                format_patch(&[&format!("--interdiff={old}")])?
             }
-            (None, Some(diff_to)) => {
-                format_patch(&[&format!("--interdiff={diff_to}")])?
-            },
+            (None, Some(diff_to)) => format_patch(&[&format!("--interdiff={diff_to}")])?,
         };
 
-        let cover_letter = branch_dir.join(COVER_LETTER_NAME);
+        let cover_letter = series.cover_letter_path();
         if !cover_letter.exists() {
             let mut cover_letter_template = format!("Title: \n\nBranch: {branch}\n");
             if let Some(ci_link) = &ci_link {
@@ -467,6 +2900,48 @@ impl FormatPatch {
                 .wrap_err("Could not write cover letter")?;
         }
 
+        let mut changes: Vec<_> = dropped.iter().map(|s| format!("applied: {s}")).collect();
+        changes.extend(
+            stored_metadata
+                .rebases
+                .iter()
+                .filter(|r| r.version == stored_version.number)
+                .map(|r| format!("Rebased on {} ({})", r.reference, &r.onto[..12])),
+        );
+        // Formatting a version again with --force only adds the changes not listed yet
+        let content = std::fs::read_to_string(&cover_letter)
+            .into_diagnostic()
+            .wrap_err("Could not read the cover letter")?;
+        let mut lines: Vec<String> = content.lines().map(String::from).collect();
+        let changes: Vec<_> = changes
+            .into_iter()
+            .map(|change| format!("- {change}"))
+            .filter(|change| !lines.contains(change))
+            .collect();
+        if !changes.is_empty() {
+            let header = format!("Changes in v{}:", stored_version.number);
+            match lines.iter().position(|l| *l == header) {
+                Some(start) => {
+                    let end = start
+                        + 1
+                        + lines[start + 1..]
+                            .iter()
+                            .take_while(|l| l.starts_with("- "))
+                            .count();
+                    lines.splice(end..end, changes);
+                }
+                None => {
+                    lines.push(String::new());
+                    lines.push(header);
+                    lines.extend(changes);
+                }
+            }
+
+            std::fs::write(&cover_letter, lines.join("\n") + "\n")
+                .into_diagnostic()
+                .wrap_err("Could not add the changes to the cover letter")?;
+        }
+
         std::process::Command::new(config.editor)
             .arg(&cover_letter)
             .status()
@@ -476,44 +2951,39 @@ impl FormatPatch {
         let cover_letter = std::fs::read_to_string(cover_letter)
             .into_diagnostic()
             .wrap_err("Error while reading back the cover letter")?;
-
-        let Some((title, body)) = cover_letter.split_once('\n') else {
-            return Err(miette!("Missing title newline"));
-        };
-
-        let Some(title) = title.strip_prefix("Title: ") else {
-            return Err(miette!("Missing `Title: ` prefix"));
-        };
-
-        let mut cover_letter = None;
-        for entry in Path::new(version_dir)
-            .read_dir()
-            .into_diagnostic()
-            .wrap_err("Could not read patch directory")?
-        {
-            let entry = entry
-                .into_diagnostic()
-                .wrap_err("Error while reading patch directory")?;
-
-            if entry
-                .file_name()
-                .as_encoded_bytes()
-                .ends_with(b"cover-letter.patch")
-            {
-                cover_letter = Some(entry.file_name());
-            }
+        let CoverLetter {
+            title, body, todos, ..
+        } = CoverLetter::parse(&cover_letter)?;
+        if !todos.is_empty() {
+            eprintln!(
+                "WARNING: {} TODOs are pending in the cover letter",
+                todos.len()
+            );
         }
 
-        let cover_letter = Path::new(version_dir)
-            .join(cover_letter.ok_or(miette!("Did not find cover letter in {version_dir}"))?);
+        let cover_letter = stored_version
+            .cover_letter()?
+            .ok_or(miette!("Did not find cover letter in {version_dir}"))?;
 
         let cover_letter_content = std::fs::read_to_string(&cover_letter)
             .into_diagnostic()
             .wrap_err("Could not read patchset cover letter")?;
 
+        let mut blurb = body.trim().to_string();
+        if let Some(parent) = &parent {
+            let sent = match &parent.message_id {
+                Some(message_id) => format!(", sent as {message_id}"),
+                None => String::new(),
+            };
+            blurb += &format!(
+                "\n\nThis series applies on top of {} v{}{sent}.",
+                parent.name, parent.version
+            );
+        }
+
         let cover_letter_content = cover_letter_content
             .replace("*** SUBJECT HERE ***", title.trim())
-            .replace("*** BLURB HERE ***", body.trim());
+            .replace("*** BLURB HERE ***", blurb.trim());
 
         let mut file = OpenOptions::new()
             .write(true)
@@ -527,55 +2997,149 @@ impl FormatPatch {
 
         std::mem::forget(_version_dir);
 
+        let mut metadata = SeriesMetadata::load(series.dir())?;
+        metadata.range = range;
+        metadata.parent = parent.as_ref().map(|p| p.name.clone());
+        let tip = metadata.tip(&branch);
+        let version_metadata = metadata.version_mut(stored_version.number);
+        version_metadata.parent_version = parent.map(|p| p.version);
+        version_metadata.emails.clear();
+        version_metadata.formatted = Some(chrono::Local::now().fixed_offset());
+        version_metadata.head = Some(git_cd(&["rev-parse", &tip])?);
+        version_metadata.ci = ci_link;
+        version_metadata.base = patch_id::patch_commits(&stored_version.patches()?)?
+            .first()
+            .and_then(|first| git_cd(&["rev-parse", &format!("{first}^")]).ok());
+        metadata.save(series.dir())?;
+
         Ok(())
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 struct GsmConfig {
     sendmail_args: Option<Vec<String>>,
+    #[serde(default)]
+    transport: TransportKind,
+    smtp: Option<SmtpConfig>,
+    /// Destination of the file, mbox and maildir transports
+    sink: Option<PathBuf>,
+    /// Seconds to wait between each email
+    throttle: Option<u64>,
+    /// Compute the recipients of each patch from the files it modifies
+    maintainers: Option<MaintainersConfig>,
+    #[serde(default)]
+    reviewers: ReviewersConfig,
+    /// Names standing for lists of addresses
+    #[serde(default)]
+    aliases: AddressBook,
+    /// Sender of the emails, defaults to `sendemail.from` or the committer identity
+    from: Option<String>,
+    #[serde(default)]
+    to: Vec<String>,
+    #[serde(default)]
+    cc: Vec<String>,
     editor: String,
     repo_url_base: String,
     component: Option<String>,
     ci_url: Option<String>,
     interdiff_base: Option<String>,
+    /// Remote the series are sent upstream from, defaults to the upstream remote of the branch
+    remote: Option<String>,
+    /// Branch the series are based on, defaults to the `HEAD` of the remote
+    default_branch: Option<String>,
+    #[serde(default)]
+    sync: SyncConfig,
 }
 
-fn latest_version(branch_dir: &Path) -> Result<Option<u64>> {
-    let mut dir_content = branch_dir
-        .read_dir()
-        .into_diagnostic()
-        .wrap_err("could not read branch dir")?
-        .peekable();
-
-    match dir_content.peek() {
-        Some(_) => Ok(Some(
-            dir_content
-                .filter_map(|e| {
-                    let entry = match e.into_diagnostic().wrap_err("Could not read entry") {
-                        Ok(e) => e,
-                        Err(e) => return Some(Err(e)),
-                    };
+impl GsmConfig {
+    /// The configured remote, else the remote `branch` tracks, else `origin`
+    fn remote(&self, git_cd: impl Fn(&[&str]) -> Result<String>, branch: &str) -> String {
+        self.remote
+            .clone()
+            .or_else(|| git_cd(&["config", "--get", &format!("branch.{branch}.remote")]).ok())
+            .filter(|remote| remote != ".")
+            .unwrap_or_else(|| String::from("origin"))
+    }
 
-                    let name = entry.file_name();
-                    let name = name.to_str().expect("patch set entry is not utf8");
+    /// The configured default branch, else the one `HEAD` of `remote` points to, else `master`
+    fn default_branch(&self, git_cd: impl Fn(&[&str]) -> Result<String>, remote: &str) -> String {
+        self.default_branch
+            .clone()
+            .or_else(|| {
+                let head = git_cd(&[
+                    "symbolic-ref",
+                    "--quiet",
+                    "--short",
+                    &format!("refs/remotes/{remote}/HEAD"),
+                ])
+                .ok()?;
+                head.strip_prefix(&format!("{remote}/")).map(String::from)
+            })
+            .unwrap_or_else(|| String::from("master"))
+    }
 
-                    if name == "cover-letter" {
-                        None
-                    } else {
-                        Some(Ok(name.parse().expect("version is not an int")))
-                    }
-                })
-                .try_fold(0, |cur, version| -> Result<_> {
-                    let version = version?;
+    /// The reference `branch` is compared to: `interdiff_base`, else the default branch of the
+    /// remote
+    fn upstream(&self, git_cd: impl Fn(&[&str]) -> Result<String>, branch: &str) -> String {
+        match &self.interdiff_base {
+            Some(base) => base.clone(),
+            None => {
+                let remote = self.remote(&git_cd, branch);
+                let default_branch = self.default_branch(&git_cd, &remote);
+                format!("{remote}/{default_branch}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+struct SyncConfig {
+    /// Remote the patch store is mirrored to
+    remote: Option<String>,
+}
+
+/// The part of the configuration needed to find the patch store, which can hold the rest
+///
+/// The location is overridden by `git config gsm.store`.
+#[derive(Debug, serde::Deserialize)]
+struct StoreConfig {
+    #[serde(default)]
+    store: StoreLocation,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+struct ReviewersConfig {
+    /// Cc the reviewers and participants of the previous versions
+    #[serde(default)]
+    cc: bool,
+    /// Addresses never to Cc automatically, or whole domains with `@domain`
+    #[serde(default)]
+    ignore: Vec<String>,
+}
 
-                    Ok(std::cmp::max(version, cur))
-                })?,
-        )),
-        None => Ok(None),
+impl ReviewersConfig {
+    fn is_ignored(&self, address: &str) -> bool {
+        let address = mail::address_of(address).to_lowercase();
+        self.ignore.iter().any(|ignored| {
+            let ignored = ignored.to_lowercase();
+            match ignored.starts_with('@') {
+                true => address.ends_with(&ignored),
+                false => mail::address_of(&ignored) == address,
+            }
+        })
     }
 }
 
+/// Parse a version given on the command line, as `vN` or `N`
+fn parse_version(version: &str) -> Result<u64, String> {
+    version
+        .strip_prefix('v')
+        .unwrap_or(version)
+        .parse()
+        .map_err(|_| format!("invalid version `{version}`, expected `vN` or `N`"))
+}
+
 fn git_bare(args: Vec<&str>) -> Result<String> {
     let out = duct::cmd("git", args)
         .stderr_to_stdout()
@@ -589,7 +3153,7 @@ fn git_bare(args: Vec<&str>) -> Result<String> {
     let output = output.trim();
 
     if !out.status.success() {
-        return Err(miette!("{output}").wrap_err("git command failed"));
+        Err(miette!("{output}").wrap_err("git command failed"))
     } else {
         Ok(output.to_string())
     }
@@ -607,7 +3171,6 @@ fn main() -> Result<()> {
             "--show-toplevel",
         ])?))
     })?;
-    let patch_dir = repo_root.join(".patches");
 
     let git_cd = |args: &[&str]| {
         let mut a = vec![
@@ -621,11 +3184,35 @@ fn main() -> Result<()> {
         git_bare(a)
     };
 
-    let config: GsmConfig = Config::builder()
-        .add_source(
-            config::File::from(project_dir.config_dir().join("config.toml")).required(false),
-        )
-        .add_source(config::File::from(patch_dir.join("config.toml")).required(false))
+    let in_tree_dir = repo_root.join(store::IN_TREE_NAME);
+    let config_builder = || {
+        Config::builder()
+            .add_source(
+                config::File::from(project_dir.config_dir().join("config.toml")).required(false),
+            )
+            .add_source(config::File::from(in_tree_dir.join(store::CONFIG_NAME)).required(false))
+    };
+
+    // The location can be set per repository in the git configuration, so that it does not need
+    // a file in the working tree
+    let location = match git_cd(&["config", "--get", "gsm.store"]) {
+        Ok(location) => StoreLocation::from_str(&location, true)
+            .map_err(|e| miette!("Invalid gsm.store `{location}`: {e}"))?,
+        Err(_) => {
+            config_builder()
+                .build()
+                .into_diagnostic()
+                .wrap_err("Failed to read the configuration")?
+                .try_deserialize::<StoreConfig>()
+                .into_diagnostic()
+                .wrap_err("Failed to deserialize the configuration")?
+                .store
+        }
+    };
+    let patch_dir = location.path(git_cd, &repo_root, project_dir.data_dir())?;
+
+    let config: GsmConfig = config_builder()
+        .add_source(config::File::from(patch_dir.join(store::CONFIG_NAME)).required(false))
         .build()
         .into_diagnostic()
         .wrap_err("Failed to read the configuration")?
@@ -633,14 +3220,30 @@ fn main() -> Result<()> {
         .into_diagnostic()
         .wrap_err("Failed to deserialize the configuration")?;
 
-    std::fs::create_dir_all(&patch_dir)
-        .into_diagnostic()
-        .wrap_err("could not create patch directory")?;
+    let store = PatchStore::open(patch_dir)?;
 
     match args.command {
-        Command::FormatPatch(args) => args.run(config, git_cd, &patch_dir),
-        Command::List(list) => list.run(config, git_cd, &patch_dir),
-        Command::Send(send) => send.run(config, git_cd, &patch_dir),
-        Command::Delete(delete) => delete.run(config, git_cd, &patch_dir),
+        Command::FormatPatch(args) => args.run(config, git_cd, &store),
+        Command::List(list) => list.run(config, git_cd, &store),
+        Command::Send(send) => send.run(config, git_cd, &store),
+        Command::Delete(delete) => delete.run(config, git_cd, &store),
+        Command::Outbox(outbox) => outbox.run(config, git_cd, &store),
+        Command::Import(import) => import.run(config, git_cd, &store),
+        Command::Status(status) => status.run(config, git_cd, &store),
+        Command::Show(show) => show.run(config, git_cd, &store),
+        Command::Diff(diff) => diff.run(config, git_cd, &store),
+        Command::Sync(sync) => sync.run(config, git_cd, &store),
+        Command::Archive(archive) => archive.run(config, git_cd, &store),
+        Command::Restore(restore) => restore.run(config, git_cd, &store),
+        Command::Prune(prune) => prune.run(config, git_cd, &store),
+        Command::Rename(rename) => rename.run(config, git_cd, &store),
+        Command::Rebase(rebase) => rebase.run(config, git_cd, &store),
+        Command::Checkout(checkout) => checkout.run(config, git_cd, &store),
+        Command::Migrate(migrate) => {
+            let dest = migrate
+                .to
+                .path(git_cd, &repo_root, project_dir.data_dir())?;
+            migrate.run(git_cd, &store, &dest)
+        }
     }
 }
@@ -0,0 +1,305 @@
+use std::path::Path;
+
+use miette::{Context, IntoDiagnostic, Result};
+
+use crate::mail::{Field, Recipient};
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct MaintainersConfig {
+    /// Kernel-style MAINTAINERS file, relative to the repository root
+    file: Option<String>,
+    /// Addresses to send patches touching files matching a glob to
+    #[serde(default)]
+    paths: Vec<PathRecipients>,
+}
+
+// This is not a map from glob to addresses as the configuration keys are lowercased
+#[derive(Debug, Clone, serde::Deserialize)]
+struct PathRecipients {
+    glob: String,
+    #[serde(default)]
+    to: Vec<String>,
+    #[serde(default)]
+    cc: Vec<String>,
+}
+
+/// An entry of the MAINTAINERS file
+#[derive(Debug, Default)]
+struct Section {
+    name: String,
+    recipients: Vec<(String, Field, &'static str)>,
+    files: Vec<String>,
+    excludes: Vec<String>,
+}
+
+impl Section {
+    /// The first file covered by this section, if any
+    fn matches<'a>(&self, files: &'a [String]) -> Option<&'a str> {
+        files
+            .iter()
+            .find(|f| {
+                self.files.iter().any(|p| pattern_matches(p, f))
+                    && !self.excludes.iter().any(|p| pattern_matches(p, f))
+            })
+            .map(|f| f.as_str())
+    }
+}
+
+pub struct Maintainers {
+    sections: Vec<Section>,
+}
+
+impl Maintainers {
+    pub fn load(config: &MaintainersConfig, repo_root: &Path) -> Result<Self> {
+        let mut sections = match &config.file {
+            None => Vec::new(),
+            Some(file) => {
+                let path = repo_root.join(file);
+                let content = std::fs::read_to_string(&path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not read maintainers file {path:?}"))?;
+                parse(&content)
+            }
+        };
+
+        sections.extend(config.paths.iter().map(|path| {
+            let to = path.to.iter().map(|a| (a.clone(), Field::To, "listed for"));
+            let cc = path.cc.iter().map(|a| (a.clone(), Field::Cc, "listed for"));
+
+            Section {
+                name: format!("`{}` in the configuration", path.glob),
+                recipients: to.chain(cc).collect(),
+                files: vec![path.glob.clone()],
+                excludes: Vec::new(),
+            }
+        }));
+
+        Ok(Self { sections })
+    }
+
+    /// Recipients of a patch modifying `files`
+    pub fn recipients(&self, files: &[String]) -> Vec<Recipient> {
+        self.sections
+            .iter()
+            .filter_map(|section| Some((section, section.matches(files)?)))
+            .flat_map(|(section, file)| {
+                section
+                    .recipients
+                    .iter()
+                    .map(move |(address, field, role)| {
+                        Recipient::new(address, *field, format!("{role} {} ({file})", section.name))
+                    })
+            })
+            .collect()
+    }
+}
+
+fn parse(content: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut current: Option<Section> = None;
+
+    for line in content.lines() {
+        let tag = line
+            .split_once(':')
+            .filter(|(tag, _)| tag.len() == 1 && tag.chars().all(|c| c.is_ascii_uppercase()));
+
+        match (tag, &mut current) {
+            (Some((tag, value)), Some(section)) => {
+                let value = value.trim().to_string();
+                match tag {
+                    "M" => section.recipients.push((value, Field::To, "maintainer of")),
+                    "R" => section.recipients.push((value, Field::Cc, "reviewer of")),
+                    "L" => {
+                        // Lists can have a comment, like `(moderated for non-subscribers)`
+                        let list = value.split_whitespace().next().unwrap_or_default();
+                        section
+                            .recipients
+                            .push((list.to_string(), Field::Cc, "mailing list of"))
+                    }
+                    "F" => section.files.push(value),
+                    "X" => section.excludes.push(value),
+                    _ => (),
+                }
+            }
+            (Some(_), None) => (),
+            (None, _) if line.trim().is_empty() => sections.extend(current.take()),
+            (None, _) => {
+                sections.extend(current.take());
+                current = Some(Section {
+                    name: line.trim().to_string(),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    sections.extend(current);
+
+    sections.retain(|s| !s.files.is_empty());
+    sections
+}
+
+/// Match a file against a MAINTAINERS pattern
+///
+/// Like in the kernel, a pattern ending with `/` covers the whole directory, `*` and `?` do not
+/// cross directories, and a plain path matches the file or everything below it. `**` matches any
+/// number of directories.
+fn pattern_matches(pattern: &str, file: &str) -> bool {
+    if pattern.ends_with('/') {
+        return file.starts_with(pattern);
+    }
+
+    if !pattern.contains(['*', '?']) {
+        return file == pattern
+            || file
+                .strip_prefix(pattern)
+                .is_some_and(|rest| rest.starts_with('/'));
+    }
+
+    glob_matches(pattern.as_bytes(), file.as_bytes())
+}
+
+fn glob_matches(pattern: &[u8], file: &[u8]) -> bool {
+    match pattern {
+        [] => file.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => (0..=file.len())
+            .filter(|&i| i == 0 || file[i - 1] == b'/')
+            .any(|i| glob_matches(rest, &file[i..])),
+        [b'*', b'*', rest @ ..] => (0..=file.len()).any(|i| glob_matches(rest, &file[i..])),
+        [b'*', rest @ ..] => (0..=file.len())
+            .take_while(|&i| i == 0 || file[i - 1] != b'/')
+            .any(|i| glob_matches(rest, &file[i..])),
+        [b'?', rest @ ..] => match file {
+            [c, file @ ..] if *c != b'/' => glob_matches(rest, file),
+            _ => false,
+        },
+        [p, rest @ ..] => match file {
+            [c, file @ ..] if c == p => glob_matches(rest, file),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, file: &str) -> bool {
+        glob_matches(pattern.as_bytes(), file.as_bytes())
+    }
+
+    #[test]
+    fn glob_star_stays_in_directory() {
+        assert!(glob("src/*.rs", "src/main.rs"));
+        assert!(glob("src/*", "src/main.rs"));
+        assert!(!glob("src/*.rs", "src/mail/mod.rs"));
+        assert!(!glob("*.rs", "src/main.rs"));
+        assert!(glob("src/m?in.rs", "src/main.rs"));
+        assert!(!glob("src?main.rs", "src/main.rs"));
+    }
+
+    #[test]
+    fn glob_double_star_crosses_directories() {
+        assert!(glob("**/*.rs", "main.rs"));
+        assert!(glob("**/*.rs", "src/mail/mod.rs"));
+        assert!(glob("src/**/mod.rs", "src/mod.rs"));
+        assert!(glob("src/**/mod.rs", "src/a/b/mod.rs"));
+        assert!(!glob("src/**/mod.rs", "src/amod.rs"));
+        assert!(glob("src/**", "src/a/b/c.rs"));
+        assert!(!glob("src/**", "tests/a.rs"));
+    }
+
+    #[test]
+    fn pattern_trailing_slash_covers_directory() {
+        assert!(pattern_matches("drivers/net/", "drivers/net/a.c"));
+        assert!(pattern_matches("drivers/net/", "drivers/net/b/c.c"));
+        assert!(!pattern_matches("drivers/net/", "drivers/network.c"));
+        assert!(!pattern_matches("drivers/net/", "drivers/net"));
+    }
+
+    #[test]
+    fn pattern_plain_path_covers_file_or_directory() {
+        assert!(pattern_matches("drivers/net", "drivers/net"));
+        assert!(pattern_matches("drivers/net", "drivers/net/a.c"));
+        assert!(!pattern_matches("drivers/net", "drivers/network.c"));
+        assert!(pattern_matches("drivers/*/a.c", "drivers/net/a.c"));
+        assert!(!pattern_matches("drivers/*.c", "drivers/net/a.c"));
+    }
+
+    const MAINTAINERS: &str = "\
+Descriptions of the fields
+	M: Mail patches to: FullName <address@domain>
+
+NETWORKING
+M:	Net Maintainer <net@example.com>
+R:	Net Reviewer <review@example.com>
+L:	netdev@example.com (moderated for non-subscribers)
+S:	Maintained
+F:	net/
+F:	include/net/*.h
+X:	net/wireless/
+
+WIRELESS
+M:	wifi@example.com
+F:	net/wireless/
+
+NO FILES
+M:	nobody@example.com
+";
+
+    #[test]
+    fn parse_sections() {
+        let sections = parse(MAINTAINERS);
+
+        let names: Vec<_> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["NETWORKING", "WIRELESS"]);
+
+        let networking = &sections[0];
+        assert_eq!(
+            networking.recipients,
+            [
+                (
+                    "Net Maintainer <net@example.com>".to_string(),
+                    Field::To,
+                    "maintainer of"
+                ),
+                (
+                    "Net Reviewer <review@example.com>".to_string(),
+                    Field::Cc,
+                    "reviewer of"
+                ),
+                (
+                    "netdev@example.com".to_string(),
+                    Field::Cc,
+                    "mailing list of"
+                ),
+            ]
+        );
+        assert_eq!(networking.files, ["net/", "include/net/*.h"]);
+        assert_eq!(networking.excludes, ["net/wireless/"]);
+    }
+
+    #[test]
+    fn excludes_skip_files() {
+        let sections = parse(MAINTAINERS);
+        let networking = &sections[0];
+
+        let files = ["net/wireless/a.c".to_string(), "net/core.c".to_string()];
+        assert_eq!(networking.matches(&files), Some("net/core.c"));
+        assert_eq!(networking.matches(&files[..1]), None);
+        assert_eq!(sections[1].matches(&files), Some("net/wireless/a.c"));
+
+        let maintainers = Maintainers { sections };
+        let recipients: Vec<_> = maintainers
+            .recipients(&files[..1])
+            .into_iter()
+            .map(|r| (r.address, r.reason))
+            .collect();
+        assert_eq!(
+            recipients,
+            [(
+                "wifi@example.com".to_string(),
+                "maintainer of WIRELESS (net/wireless/a.c)".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+use miette::{Context, IntoDiagnostic, Result};
+
+use crate::{mail::address_of, store::Tombstone};
+
+/// Name of the metadata file in a series directory
+pub const SERIES_METADATA_NAME: &str = "series.toml";
+
+/// Persistent information about a series, stored alongside its versions
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SeriesMetadata {
+    /// Recipients of every version of the series, addresses or aliases
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub to: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cc: Vec<String>,
+    /// Commits of the series as `base..tip`, when they are not the branch named after the series
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+    /// Series this one builds on, which must be applied first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// When the series was last restored or renamed, which outdates older removals of a series of
+    /// the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revived: Option<DateTime<FixedOffset>>,
+    /// Rebases of the branch done with `gsm rebase`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rebases: Vec<RebaseRecord>,
+    #[serde(default)]
+    pub versions: Vec<VersionMetadata>,
+}
+
+/// A rebase of the branch of a series between two versions
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RebaseRecord {
+    /// Version the rebase was done for
+    pub version: u64,
+    pub date: DateTime<FixedOffset>,
+    /// What the branch was rebased onto, as a reference name
+    pub reference: String,
+    /// Commit the branch was based on before the rebase
+    pub from: String,
+    /// Commit the branch is based on after the rebase
+    pub onto: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct VersionMetadata {
+    pub version: u64,
+    /// When the version was formatted
+    pub formatted: Option<DateTime<FixedOffset>>,
+    /// Commit of the branch the version was formatted from
+    pub head: Option<String>,
+    /// Parent commit of the first patch of the version
+    pub base: Option<String>,
+    /// Link to the CI run of the version
+    pub ci: Option<String>,
+    /// Version of the parent series this version was based on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_version: Option<u64>,
+    /// Outcome of each email sent for this version, in sending order
+    #[serde(default)]
+    pub emails: Vec<EmailRecord>,
+    /// People who gave a review trailer (Reviewed-by, Acked-by, ...) on this version
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reviewers: Vec<String>,
+    /// People who took part in the discussion of this version
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub participants: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EmailRecord {
+    pub file: String,
+    pub message_id: String,
+    pub date: String,
+    /// Set when the email could not be sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl EmailRecord {
+    pub fn is_sent(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl VersionMetadata {
+    pub fn new(version: u64) -> Self {
+        Self {
+            version,
+            formatted: None,
+            head: None,
+            base: None,
+            ci: None,
+            parent_version: None,
+            emails: Vec::new(),
+            reviewers: Vec::new(),
+            participants: Vec::new(),
+        }
+    }
+
+    /// Whether the email with this Message-ID was sent for this version
+    pub fn has_message(&self, message_id: &str) -> bool {
+        self.emails
+            .iter()
+            .any(|e| e.is_sent() && e.message_id == message_id)
+    }
+
+    pub fn sent(&self, file: &str) -> Option<&EmailRecord> {
+        self.emails.iter().find(|e| e.file == file && e.is_sent())
+    }
+
+    /// Combine the records of the same formatted version, keeping the successful sends
+    fn merge(&mut self, other: VersionMetadata) {
+        for email in other.emails {
+            match self.emails.iter().find(|e| e.file == email.file) {
+                Some(current) if current.is_sent() || !email.is_sent() => (),
+                _ => self.record(email),
+            }
+        }
+        for reviewer in &other.reviewers {
+            add_address(&mut self.reviewers, reviewer);
+        }
+        for participant in &other.participants {
+            add_address(&mut self.participants, participant);
+        }
+        self.head = self.head.take().or(other.head);
+        self.base = self.base.take().or(other.base);
+        self.ci = self.ci.take().or(other.ci);
+    }
+
+    /// Record the outcome of sending an email, replacing any previous attempt
+    pub fn record(&mut self, record: EmailRecord) {
+        self.emails.retain(|e| e.file != record.file);
+        self.emails.push(record);
+    }
+}
+
+impl SeriesMetadata {
+    pub fn load(branch_dir: &Path) -> Result<Self> {
+        let path = branch_dir.join(SERIES_METADATA_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .into_diagnostic()
+            .wrap_err("Could not read series metadata")?;
+
+        toml::from_str(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid series metadata in {path:?}"))
+    }
+
+    pub fn save(&self, branch_dir: &Path) -> Result<()> {
+        let content = toml::to_string_pretty(self)
+            .into_diagnostic()
+            .wrap_err("Could not serialize series metadata")?;
+
+        std::fs::write(branch_dir.join(SERIES_METADATA_NAME), content)
+            .into_diagnostic()
+            .wrap_err("Could not write series metadata")
+    }
+
+    pub fn version(&self, version: u64) -> Option<&VersionMetadata> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    /// Merge the metadata of the same series coming from another store
+    ///
+    /// Versions are merged one by one: the most recently formatted one wins, and when both were
+    /// formatted at the same time their sending records and reviewers are combined.
+    pub fn merge(&mut self, other: SeriesMetadata) {
+        if self.range.is_none() {
+            self.range = other.range;
+        }
+        if self.parent.is_none() {
+            self.parent = other.parent;
+        }
+        self.revived = self.revived.max(other.revived);
+        for rebase in other.rebases {
+            if !self.rebases.contains(&rebase) {
+                self.rebases.push(rebase);
+            }
+        }
+        self.rebases.sort_by_key(|r| r.date);
+        for address in &other.to {
+            add_address(&mut self.to, address);
+        }
+        for address in &other.cc {
+            add_address(&mut self.cc, address);
+        }
+
+        for version in other.versions {
+            let Some(current) = self
+                .versions
+                .iter_mut()
+                .find(|v| v.version == version.version)
+            else {
+                self.versions.push(version);
+                continue;
+            };
+
+            match version.formatted.cmp(&current.formatted) {
+                std::cmp::Ordering::Greater => *current = version,
+                std::cmp::Ordering::Less => (),
+                std::cmp::Ordering::Equal => current.merge(version),
+            }
+        }
+
+        self.versions.sort_by_key(|v| v.version);
+    }
+
+    /// Last time the series was formatted or revived
+    pub fn last_change(&self) -> Option<DateTime<FixedOffset>> {
+        self.versions
+            .iter()
+            .filter_map(|v| v.formatted)
+            .chain(self.revived)
+            .max()
+    }
+
+    /// Whether `tombstone` removed this series after its last change
+    pub fn is_buried_by(&self, tombstone: &Tombstone) -> bool {
+        self.last_change()
+            .is_none_or(|changed| changed <= tombstone.removed)
+    }
+
+    /// Tip of the series: the end of its range, else the branch `name` of the series
+    pub fn tip(&self, name: &str) -> String {
+        match self.range.as_deref().and_then(|r| r.split_once("..")) {
+            Some((_, "")) => String::from("HEAD"),
+            Some((_, tip)) => tip.to_string(),
+            None => format!("refs/heads/{name}"),
+        }
+    }
+
+    pub fn version_mut(&mut self, version: u64) -> &mut VersionMetadata {
+        match self.versions.iter().position(|v| v.version == version) {
+            Some(idx) => &mut self.versions[idx],
+            None => {
+                self.versions.push(VersionMetadata::new(version));
+                self.versions.sort_by_key(|v| v.version);
+                self.versions
+                    .iter_mut()
+                    .find(|v| v.version == version)
+                    .expect("version was just inserted")
+            }
+        }
+    }
+}
+
+/// Add `address` to `list` unless it is already present
+pub fn add_address(list: &mut Vec<String>, address: &str) {
+    let bare = address_of(address).to_lowercase();
+    if !list.iter().any(|a| address_of(a).to_lowercase() == bare) {
+        list.push(address.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn email(file: &str, error: Option<&str>) -> EmailRecord {
+        EmailRecord {
+            file: file.to_string(),
+            message_id: format!("<{file}@example.com>"),
+            date: "Mon, 1 Jan 2024 00:00:00 +0000".to_string(),
+            error: error.map(String::from),
+        }
+    }
+
+    fn version(number: u64, formatted: &str, emails: Vec<EmailRecord>) -> VersionMetadata {
+        VersionMetadata {
+            formatted: Some(date(formatted)),
+            emails,
+            ..VersionMetadata::new(number)
+        }
+    }
+
+    fn series(versions: Vec<VersionMetadata>) -> SeriesMetadata {
+        SeriesMetadata {
+            versions,
+            ..Default::default()
+        }
+    }
+
+    fn sent_files(version: &VersionMetadata) -> Vec<&str> {
+        let mut files: Vec<_> = version
+            .emails
+            .iter()
+            .filter(|e| e.is_sent())
+            .map(|e| e.file.as_str())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn merge_keeps_newest_formatted_version() {
+        let mut local = series(vec![
+            version(1, "2024-01-01T00:00:00Z", vec![email("a", None)]),
+            version(2, "2024-01-02T00:00:00Z", vec![email("b", None)]),
+        ]);
+        let remote = series(vec![
+            // Formatted again on the other machine, and not sent yet
+            version(1, "2024-01-03T00:00:00Z", vec![]),
+            // Formatted before the local one
+            version(2, "2024-01-01T12:00:00Z", vec![email("c", None)]),
+        ]);
+
+        local.merge(remote);
+
+        let v1 = local.version(1).unwrap();
+        assert_eq!(v1.formatted, Some(date("2024-01-03T00:00:00Z")));
+        assert!(v1.emails.is_empty());
+        let v2 = local.version(2).unwrap();
+        assert_eq!(v2.formatted, Some(date("2024-01-02T00:00:00Z")));
+        assert_eq!(sent_files(v2), ["b"]);
+    }
+
+    #[test]
+    fn merge_combines_same_formatted_version() {
+        let formatted = "2024-01-01T00:00:00Z";
+        let mut local = series(vec![version(
+            1,
+            formatted,
+            vec![
+                email("a", None),
+                email("b", Some("refused")),
+                email("c", None),
+            ],
+        )]);
+        local.versions[0].reviewers = vec!["Alice <alice@example.com>".to_string()];
+        let mut remote = series(vec![version(
+            1,
+            formatted,
+            vec![
+                email("a", Some("timeout")),
+                email("b", None),
+                email("d", Some("refused")),
+            ],
+        )]);
+        remote.versions[0].reviewers = vec![
+            "alice@example.com".to_string(),
+            "Bob <bob@example.com>".to_string(),
+        ];
+        remote.versions[0].ci = Some("https://ci.example.com/1".to_string());
+
+        local.merge(remote);
+
+        let v1 = local.version(1).unwrap();
+        // A successful send on either side wins over a failure
+        assert_eq!(sent_files(v1), ["a", "b", "c"]);
+        assert!(v1.emails.iter().any(|e| e.file == "d" && !e.is_sent()));
+        assert_eq!(
+            v1.reviewers,
+            ["Alice <alice@example.com>", "Bob <bob@example.com>"]
+        );
+        assert_eq!(v1.ci.as_deref(), Some("https://ci.example.com/1"));
+    }
+
+    #[test]
+    fn merge_adds_missing_versions_in_order() {
+        let mut local = series(vec![version(2, "2024-01-02T00:00:00Z", vec![])]);
+        let mut remote = series(vec![
+            version(1, "2024-01-01T00:00:00Z", vec![]),
+            version(3, "2024-01-03T00:00:00Z", vec![]),
+        ]);
+        remote.parent = Some("base".to_string());
+
+        local.merge(remote);
+
+        let versions: Vec<_> = local.versions.iter().map(|v| v.version).collect();
+        assert_eq!(versions, [1, 2, 3]);
+        assert_eq!(local.parent.as_deref(), Some("base"));
+    }
+
+    #[test]
+    fn merge_deduplicates_rebases() {
+        let rebase = |day: &str| RebaseRecord {
+            version: 2,
+            date: date(&format!("2024-01-{day}T00:00:00Z")),
+            reference: "master".to_string(),
+            from: "a".repeat(40),
+            onto: "b".repeat(40),
+        };
+        let mut local = SeriesMetadata {
+            rebases: vec![rebase("02")],
+            ..Default::default()
+        };
+        let remote = SeriesMetadata {
+            rebases: vec![rebase("03"), rebase("01"), rebase("02")],
+            ..Default::default()
+        };
+
+        local.merge(remote);
+
+        assert_eq!(local.rebases, [rebase("01"), rebase("02"), rebase("03")]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeZone};
+use miette::{miette, Context, IntoDiagnostic, Result};
+
+use crate::transport::TransportKind;
+
+/// Name of the outbox directory in the patch directory
+pub const OUTBOX_NAME: &str = "outbox";
+
+/// A series version waiting to be sent
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OutboxItem {
+    #[serde(skip)]
+    pub id: u64,
+    pub series: String,
+    pub version: u64,
+    pub queued: DateTime<FixedOffset>,
+    /// The item is not sent before this time
+    pub at: Option<DateTime<FixedOffset>>,
+    pub transport: Option<TransportKind>,
+    pub sink: Option<PathBuf>,
+    pub throttle: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub to: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cc: Vec<String>,
+}
+
+impl OutboxItem {
+    pub fn is_due(&self) -> bool {
+        self.at.map(|at| at <= Local::now()).unwrap_or(true)
+    }
+}
+
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    pub fn new(patch_dir: &Path) -> Self {
+        Self {
+            dir: patch_dir.join(OUTBOX_NAME),
+        }
+    }
+
+    fn item_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{id}.toml"))
+    }
+
+    /// All the items of the outbox, in queuing order
+    pub fn items(&self) -> Result<Vec<OutboxItem>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut items = Vec::new();
+        for entry in self
+            .dir
+            .read_dir()
+            .into_diagnostic()
+            .wrap_err("Could not read outbox")?
+        {
+            let entry = entry
+                .into_diagnostic()
+                .wrap_err("Could not read outbox entry")?;
+            let path = entry.path();
+
+            let Some(id) = path
+                .file_stem()
+                .filter(|_| path.extension().is_some_and(|e| e == "toml"))
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse().ok())
+            else {
+                continue;
+            };
+
+            let content = std::fs::read_to_string(&path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not read outbox item {path:?}"))?;
+            let item = toml::from_str(&content)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid outbox item {path:?}"))?;
+
+            items.push(OutboxItem { id, ..item });
+        }
+
+        items.sort_by_key(|i| i.id);
+
+        Ok(items)
+    }
+
+    /// Add an item to the outbox, returning its id
+    pub fn queue(&self, item: OutboxItem) -> Result<u64> {
+        std::fs::create_dir_all(&self.dir)
+            .into_diagnostic()
+            .wrap_err("Could not create outbox")?;
+
+        let id = self.items()?.last().map(|i| i.id + 1).unwrap_or(1);
+        self.save(&OutboxItem { id, ..item })?;
+
+        Ok(id)
+    }
+
+    /// Write an item of the outbox, replacing the one with the same id
+    pub fn save(&self, item: &OutboxItem) -> Result<()> {
+        let content = toml::to_string_pretty(item)
+            .into_diagnostic()
+            .wrap_err("Could not serialize outbox item")?;
+
+        std::fs::write(self.item_path(item.id), content)
+            .into_diagnostic()
+            .wrap_err("Could not write outbox item")
+    }
+
+    pub fn remove(&self, id: u64) -> Result<()> {
+        let path = self.item_path(id);
+        if !path.exists() {
+            return Err(miette!("No item {id} in the outbox"));
+        }
+
+        std::fs::remove_file(path)
+            .into_diagnostic()
+            .wrap_err("Could not remove outbox item")
+    }
+}
+
+/// Parse a time given on the command line
+///
+/// Accepts RFC 3339 dates, local `YYYY-MM-DD HH:MM` dates, and `HH:MM` for the next occurrence
+/// of that time.
+pub fn parse_time(time: &str) -> Result<DateTime<FixedOffset>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(time) {
+        return Ok(date);
+    }
+
+    let local = |naive: NaiveDateTime| {
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|d| d.fixed_offset())
+            .ok_or(format!("{time} does not exist in the local timezone"))
+    };
+
+    for format in ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(time, format) {
+            return local(naive);
+        }
+    }
+
+    if let Ok(naive) = NaiveTime::parse_from_str(time, "%H:%M") {
+        let now = Local::now();
+        let mut date = local(now.date_naive().and_time(naive))?;
+        if date <= now {
+            date = local((now.date_naive() + chrono::Days::new(1)).and_time(naive))?;
+        }
+        return Ok(date);
+    }
+
+    Err(format!(
+        "invalid time `{time}`, expected `HH:MM`, `YYYY-MM-DD HH:MM` or a RFC 3339 date"
+    ))
+}
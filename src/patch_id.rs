@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+
+/// The stable patch-id of a commit, identifying its changes independently of its position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchId {
+    pub id: String,
+    pub commit: String,
+}
+
+/// Compute the patch-ids of a stream of patches, as produced by `git log -p` or `format-patch`
+pub fn compute(diffs: &str) -> Result<Vec<PatchId>> {
+    let out = duct::cmd("git", ["patch-id", "--stable"])
+        .stdin_bytes(diffs)
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .into_diagnostic()
+        .wrap_err("failed to launch git patch-id")?;
+
+    if !out.status.success() {
+        return Err(miette!("git patch-id failed"));
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .map(|(id, commit)| PatchId {
+            id: id.to_string(),
+            commit: commit.to_string(),
+        })
+        .collect())
+}
+
+fn is_cover_letter(patch: &Path) -> bool {
+    patch.to_string_lossy().ends_with("cover-letter.patch")
+}
+
+/// Patch-ids of stored patches, skipping the cover letter and its interdiff
+pub fn of_patches(patches: &[PathBuf]) -> Result<Vec<PatchId>> {
+    let mut diffs = String::new();
+    for patch in patches.iter().filter(|p| !is_cover_letter(p)) {
+        diffs += &std::fs::read_to_string(patch)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read patch {patch:?}"))?;
+        diffs.push('\n');
+    }
+
+    compute(&diffs)
+}
+
+/// Patch-ids of the commits in `range`, oldest first
+pub fn of_range(git_cd: impl Fn(&[&str]) -> Result<String>, range: &str) -> Result<Vec<PatchId>> {
+    let diffs = git_cd(&[
+        "log",
+        "-p",
+        "--reverse",
+        "--no-merges",
+        "--no-color",
+        "--no-ext-diff",
+        "--format=commit %H",
+        range,
+    ])?;
+
+    compute(&diffs)
+}
+
+/// Commits the stored patches were generated from, from their `From <sha>` line
+///
+/// The cover letter is skipped, as its `From` line is the tip of the series.
+pub fn patch_commits(patches: &[PathBuf]) -> Result<Vec<String>> {
+    let mut commits = Vec::new();
+    for patch in patches.iter().filter(|p| !is_cover_letter(p)) {
+        let content = std::fs::read_to_string(patch)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read patch {patch:?}"))?;
+
+        let commit = content
+            .lines()
+            .next()
+            .and_then(|l| l.strip_prefix("From "))
+            .and_then(|l| l.split_whitespace().next());
+
+        commits.extend(commit.map(String::from));
+    }
+
+    Ok(commits)
+}
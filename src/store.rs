@@ -0,0 +1,655 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+use miette::{miette, Context, IntoDiagnostic, Result, Severity};
+
+use crate::{mail, metadata::SERIES_METADATA_NAME, outbox::OUTBOX_NAME};
+
+/// Name of the cover letter template in a series directory
+pub const COVER_LETTER_NAME: &str = "cover-letter";
+/// Name of the project configuration in the patch directory
+pub const CONFIG_NAME: &str = "config.toml";
+/// Name of the directory holding archived series, laid out like the store
+pub const ARCHIVE_NAME: &str = "archive";
+/// Name of the patch directory in the working tree
+pub const IN_TREE_NAME: &str = ".patches";
+/// Name of the file listing the series removed from the store
+pub const TOMBSTONES_NAME: &str = "tombstones.toml";
+
+/// Entries of the store that are not series
+const RESERVED_NAMES: &[&str] = &[CONFIG_NAME, OUTBOX_NAME, ARCHIVE_NAME, TOMBSTONES_NAME];
+
+/// Why a series left the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemovalReason {
+    Archived,
+    Purged,
+    Renamed,
+}
+
+/// A series removed from the store
+///
+/// Tombstones are mirrored with the store so that syncing does not bring back a series removed
+/// on another machine. They only hide the versions of the series older than the removal.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Tombstone {
+    pub series: String,
+    pub removed: DateTime<FixedOffset>,
+    pub reason: RemovalReason,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct Tombstones {
+    #[serde(default)]
+    tombstones: Vec<Tombstone>,
+}
+
+/// Where the patch store of a repository lives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StoreLocation {
+    /// `.patches` at the root of the working tree
+    #[default]
+    InTree,
+    /// Inside the git directory, shared by all the worktrees of the repository
+    GitDir,
+    /// In the user data directory, keyed by repository
+    DataDir,
+}
+
+impl StoreLocation {
+    pub fn name(self) -> &'static str {
+        match self {
+            StoreLocation::InTree => "in-tree",
+            StoreLocation::GitDir => "git-dir",
+            StoreLocation::DataDir => "data-dir",
+        }
+    }
+
+    pub fn path(
+        self,
+        git_cd: impl Fn(&[&str]) -> Result<String>,
+        repo_root: &Path,
+        data_dir: &Path,
+    ) -> Result<PathBuf> {
+        let common_dir = || -> Result<PathBuf> {
+            let dir = git_cd(&["rev-parse", "--path-format=absolute", "--git-common-dir"])?;
+            PathBuf::from(dir)
+                .canonicalize()
+                .into_diagnostic()
+                .wrap_err("Could not resolve the git directory")
+        };
+
+        Ok(match self {
+            StoreLocation::InTree => repo_root.join(IN_TREE_NAME),
+            StoreLocation::GitDir => common_dir()?.join("gsm"),
+            StoreLocation::DataDir => {
+                let key = common_dir()?
+                    .to_str()
+                    .ok_or(miette!("The git directory is not utf-8"))?
+                    .to_string();
+                data_dir.join("stores").join(escape_name(&key))
+            }
+        })
+    }
+}
+
+/// Report an unexpected entry of the patch store, which is skipped
+fn warn_unknown(path: &Path, what: &str) {
+    eprintln!(
+        "{:?}",
+        miette!(
+            severity = Severity::Warning,
+            help = "remove it if it is not needed",
+            "Skipping {path:?}: {what}"
+        )
+    );
+}
+
+/// Entries that are not warned about, like editor swap files or `.DS_Store`
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Directory name of a series in the store
+///
+/// Branch names can contain `/`, which is escaped as `%2F` to keep every series at the top of
+/// the store, `%` being escaped as `%25` for the mapping to be reversible. The first character
+/// of names reserved by the store is escaped too, so that a branch named `archive` does not
+/// end up in the archive.
+fn escape_name(name: &str) -> String {
+    let escaped = name.replace('%', "%25").replace('/', "%2F");
+    match RESERVED_NAMES.contains(&escaped.as_str()) {
+        true => format!("%{:02X}{}", escaped.as_bytes()[0], &escaped[1..]),
+        false => escaped,
+    }
+}
+
+/// Branch name of a series directory, the reverse of [escape_name]
+fn unescape_name(name: &str) -> String {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = match tail {
+            [hi, lo, ..] if byte == b'%' => std::str::from_utf8(&[*hi, *lo])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Whether a directory holds a series, rather than being a namespace of the old nested layout
+fn is_series_dir(dir: &Path) -> bool {
+    let Ok(entries) = dir.read_dir() else {
+        return false;
+    };
+
+    entries.filter_map(|e| e.ok()).any(|e| {
+        let name = e.file_name();
+        let name = name.to_string_lossy();
+        name == COVER_LETTER_NAME
+            || name == SERIES_METADATA_NAME
+            || (name.parse::<u64>().is_ok() && e.path().is_dir())
+    })
+}
+
+/// The directory holding every formatted series
+pub struct PatchStore {
+    dir: PathBuf,
+}
+
+impl PatchStore {
+    /// Open the store, creating it if needed
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .into_diagnostic()
+            .wrap_err("could not create patch directory")?;
+
+        let store = Self { dir };
+        store.migrate_nested()?;
+
+        Ok(store)
+    }
+
+    /// Move the series of branches containing `/`, which used to be stored in nested directories
+    fn migrate_nested(&self) -> Result<()> {
+        fn find(dir: &Path, prefix: &str, found: &mut Vec<(String, PathBuf)>) {
+            let Ok(entries) = dir.read_dir() else {
+                return;
+            };
+
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                let Some(name) = entry.file_name().to_str().map(String::from) else {
+                    continue;
+                };
+                if !path.is_dir() || is_hidden(&name) {
+                    continue;
+                }
+
+                let name = format!("{prefix}/{name}");
+                match is_series_dir(&path) {
+                    true => found.push((name, path)),
+                    false => find(&path, &name, found),
+                }
+            }
+        }
+
+        let mut nested = Vec::new();
+        for entry in self
+            .dir
+            .read_dir()
+            .into_diagnostic()
+            .wrap_err("Could not read patch dir")?
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let Some(name) = entry.file_name().to_str().map(String::from) else {
+                continue;
+            };
+
+            if path.is_dir()
+                && !RESERVED_NAMES.contains(&name.as_str())
+                && !is_hidden(&name)
+                && !is_series_dir(&path)
+            {
+                find(&path, &name, &mut nested);
+            }
+        }
+
+        for (name, path) in nested {
+            let series = self.series(&name);
+            if series.dir.exists() {
+                eprintln!(
+                    "{:?}",
+                    miette!(
+                        severity = Severity::Warning,
+                        "Not moving {path:?}: the series `{name}` already exists"
+                    )
+                );
+                continue;
+            }
+
+            std::fs::rename(&path, &series.dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not move {path:?} to {:?}", series.dir))?;
+            eprintln!("Moved series `{name}` to {:?}", series.dir);
+
+            // Remove the namespace directories left empty
+            let mut parent = path.parent();
+            while let Some(dir) = parent.filter(|p| *p != self.dir) {
+                if std::fs::remove_dir(dir).is_err() {
+                    break;
+                }
+                parent = dir.parent();
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Move the whole store to `dest`, which must not contain anything
+    pub fn move_to(&self, dest: &Path) -> Result<()> {
+        if dest.exists()
+            && dest
+                .read_dir()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not read {dest:?}"))?
+                .next()
+                .is_some()
+        {
+            return Err(miette!("{dest:?} already exists and is not empty"));
+        }
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not create {parent:?}"))?;
+        }
+        if dest.exists() {
+            std::fs::remove_dir(dest)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not replace {dest:?}"))?;
+        }
+
+        // Renaming fails across filesystems, like from a repository to the data directory
+        if std::fs::rename(&self.dir, dest).is_ok() {
+            return Ok(());
+        }
+
+        copy_dir(&self.dir, dest)?;
+        std::fs::remove_dir_all(&self.dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not remove {:?}", self.dir))
+    }
+
+    /// The series removed from the store, at most one per name
+    pub fn tombstones(&self) -> Result<Vec<Tombstone>> {
+        let path = self.dir.join(TOMBSTONES_NAME);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .into_diagnostic()
+            .wrap_err("Could not read the tombstones")?;
+        let tombstones: Tombstones = toml::from_str(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid tombstones in {path:?}"))?;
+
+        Ok(tombstones.tombstones)
+    }
+
+    fn save_tombstones(&self, mut tombstones: Vec<Tombstone>) -> Result<()> {
+        tombstones.sort_by(|a, b| a.series.cmp(&b.series));
+        let content = toml::to_string_pretty(&Tombstones { tombstones })
+            .into_diagnostic()
+            .wrap_err("Could not serialize the tombstones")?;
+
+        std::fs::write(self.dir.join(TOMBSTONES_NAME), content)
+            .into_diagnostic()
+            .wrap_err("Could not write the tombstones")
+    }
+
+    /// Record that the series `name` was removed
+    pub fn bury(&self, name: &str, reason: RemovalReason) -> Result<()> {
+        self.merge_tombstones(vec![Tombstone {
+            series: name.to_string(),
+            removed: chrono::Local::now().fixed_offset(),
+            reason,
+        }])
+        .map(|_| ())
+    }
+
+    /// Add tombstones to the store, the most recent one of each series winning
+    pub fn merge_tombstones(&self, other: Vec<Tombstone>) -> Result<Vec<Tombstone>> {
+        let mut tombstones = self.tombstones()?;
+        for tombstone in other {
+            match tombstones.iter_mut().find(|t| t.series == tombstone.series) {
+                Some(current) if current.removed >= tombstone.removed => (),
+                Some(current) => *current = tombstone,
+                None => tombstones.push(tombstone),
+            }
+        }
+
+        self.save_tombstones(tombstones.clone())?;
+        Ok(tombstones)
+    }
+
+    /// The archived series, stored with the same layout
+    pub fn archive(&self) -> PatchStore {
+        PatchStore {
+            dir: self.dir.join(ARCHIVE_NAME),
+        }
+    }
+
+    /// The series of a branch, which may not have been formatted yet
+    pub fn series(&self, name: &str) -> Series {
+        Series {
+            name: name.to_string(),
+            dir: self.dir.join(escape_name(name)),
+        }
+    }
+
+    /// The series of a branch, failing if it was never formatted
+    pub fn existing_series(&self, name: &str) -> Result<Series> {
+        let series = self.series(name);
+        match series.exists() {
+            true => Ok(series),
+            false => Err(miette!("No series named `{name}`")),
+        }
+    }
+
+    /// All the series of the store, sorted by name
+    pub fn all_series(&self) -> Result<Vec<Series>> {
+        let mut series = Vec::new();
+        for entry in self
+            .dir
+            .read_dir()
+            .into_diagnostic()
+            .wrap_err("Could not read patch dir")?
+        {
+            let entry = entry
+                .into_diagnostic()
+                .wrap_err("Could not read patch dir entry")?;
+            let path = entry.path();
+
+            let Some(name) = entry.file_name().to_str().map(String::from) else {
+                warn_unknown(&path, "the name is not utf-8");
+                continue;
+            };
+
+            if RESERVED_NAMES.contains(&name.as_str()) || is_hidden(&name) {
+                continue;
+            }
+
+            if !path.is_dir() {
+                warn_unknown(&path, "not a series directory");
+                continue;
+            }
+
+            series.push(Series {
+                name: unescape_name(&name),
+                dir: path,
+            });
+        }
+
+        series.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(series)
+    }
+}
+
+/// Copy a directory recursively
+pub fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not create {to:?}"))?;
+
+    for entry in from
+        .read_dir()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not read {from:?}"))?
+    {
+        let entry = entry
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read {from:?}"))?;
+        let dest = to.join(entry.file_name());
+
+        match entry.path().is_dir() {
+            true => copy_dir(&entry.path(), &dest)?,
+            false => {
+                std::fs::copy(entry.path(), &dest)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not copy {:?}", entry.path()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A branch formatted as a patch series, holding all of its versions
+pub struct Series {
+    name: String,
+    dir: PathBuf,
+}
+
+impl Series {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    pub fn create(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .into_diagnostic()
+            .wrap_err("could not create branch dir")
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        std::fs::remove_dir_all(&self.dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not delete series `{}`", self.name))
+    }
+
+    /// Move the series to `dest`, which must not exist, in this store or another one
+    pub fn move_to(&self, dest: &Series) -> Result<()> {
+        if dest.exists() {
+            return Err(miette!("{:?} already exists", dest.dir));
+        }
+
+        if let Some(parent) = dest.dir.parent() {
+            std::fs::create_dir_all(parent)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not create {parent:?}"))?;
+        }
+
+        std::fs::rename(&self.dir, &dest.dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not move series `{}`", self.name))
+    }
+
+    pub fn cover_letter_path(&self) -> PathBuf {
+        self.dir.join(COVER_LETTER_NAME)
+    }
+
+    /// A version of the series, which may not exist
+    pub fn version(&self, number: u64) -> Version {
+        Version {
+            number,
+            dir: self.dir.join(number.to_string()),
+        }
+    }
+
+    /// A version of the series, failing if it was not formatted
+    pub fn existing_version(&self, number: u64) -> Result<Version> {
+        let version = self.version(number);
+        match version.exists() {
+            true => Ok(version),
+            false => Err(miette!(
+                "Version v{number} of `{}` does not exist",
+                self.name
+            )),
+        }
+    }
+
+    /// The formatted versions, oldest first
+    pub fn versions(&self) -> Result<Vec<Version>> {
+        if !self.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut versions = Vec::new();
+        for entry in self
+            .dir
+            .read_dir()
+            .into_diagnostic()
+            .wrap_err("could not read branch dir")?
+        {
+            let entry = entry.into_diagnostic().wrap_err("Could not read entry")?;
+            let path = entry.path();
+
+            let Some(name) = entry.file_name().to_str().map(String::from) else {
+                warn_unknown(&path, "the name is not utf-8");
+                continue;
+            };
+
+            if name == COVER_LETTER_NAME || name == SERIES_METADATA_NAME || is_hidden(&name) {
+                continue;
+            }
+
+            match name.parse() {
+                Ok(number) if path.is_dir() => versions.push(Version { number, dir: path }),
+                _ => warn_unknown(&path, "not a version of the series"),
+            }
+        }
+
+        versions.sort_by_key(|v| v.number);
+
+        Ok(versions)
+    }
+
+    pub fn latest(&self) -> Result<Option<Version>> {
+        Ok(self.versions()?.pop())
+    }
+}
+
+/// A formatted version of a series
+pub struct Version {
+    pub number: u64,
+    dir: PathBuf,
+}
+
+impl Version {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    /// The patches of the version, the cover letter being first
+    pub fn patches(&self) -> Result<Vec<PathBuf>> {
+        mail::version_patches(&self.dir)
+    }
+
+    pub fn cover_letter(&self) -> Result<Option<PathBuf>> {
+        Ok(self
+            .patches()?
+            .into_iter()
+            .find(|p| p.to_string_lossy().ends_with("cover-letter.patch")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_round_trips() {
+        for (name, escaped) in [
+            ("topic", "topic"),
+            ("a/b", "a%2Fb"),
+            ("a%2Fb", "a%252Fb"),
+            ("%", "%25"),
+            ("user/a%b/c", "user%2Fa%25b%2Fc"),
+            ("archive", "%61rchive"),
+            ("outbox", "%6Futbox"),
+            ("config.toml", "%63onfig.toml"),
+            ("%61rchive", "%2561rchive"),
+        ] {
+            assert_eq!(escape_name(name), escaped, "escaping {name}");
+            assert_eq!(unescape_name(escaped), name, "unescaping {escaped}");
+        }
+    }
+
+    #[test]
+    fn unescape_keeps_invalid_sequences() {
+        assert_eq!(unescape_name("100%"), "100%");
+        assert_eq!(unescape_name("%zz"), "%zz");
+    }
+
+    #[test]
+    fn migrate_nested_series() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let root = dir.path();
+        for path in [
+            "user/topic/1",
+            "user/deep/nested/2",
+            "plain/1",
+            "archive/old/1",
+            "taken/series/1",
+            "taken%2Fseries/1",
+        ] {
+            std::fs::create_dir_all(root.join(path)).unwrap();
+        }
+        std::fs::write(root.join("user/deep/nested").join(SERIES_METADATA_NAME), "").unwrap();
+
+        let store = PatchStore::open(root.to_path_buf()).unwrap();
+
+        let names: Vec<_> = store
+            .all_series()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name().to_string())
+            .collect();
+        for name in ["plain", "taken/series", "user/deep/nested", "user/topic"] {
+            assert!(names.iter().any(|n| n == name), "{name} not in {names:?}");
+        }
+        assert!(!names.iter().any(|n| n.starts_with("archive")));
+
+        assert!(root.join("user%2Ftopic/1").is_dir());
+        assert!(root.join("user%2Fdeep%2Fnested/2").is_dir());
+        assert!(!root.join("user").exists());
+        // Reserved directories and series in the way are left alone
+        assert!(root.join("archive/old/1").is_dir());
+        assert!(root.join("taken/series/1").is_dir());
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use temp_dir::TempDir;
+
+use crate::{
+    metadata::SeriesMetadata,
+    outbox::OUTBOX_NAME,
+    store::{self, PatchStore, Series, ARCHIVE_NAME, CONFIG_NAME},
+};
+
+/// Ref mirroring the patch store
+pub const STORE_REF: &str = "refs/gsm/store";
+
+/// Ref holding the last fetched store of `remote`
+fn remote_ref(remote: &str) -> String {
+    format!("refs/gsm/remotes/{remote}/store")
+}
+
+/// A temporary index, used to convert between the store and git trees without touching the
+/// index of the repository
+struct TempIndex {
+    _dir: TempDir,
+    index: PathBuf,
+    git_dir: String,
+}
+
+impl TempIndex {
+    fn new(git_cd: impl Fn(&[&str]) -> Result<String>) -> Result<Self> {
+        let dir = TempDir::new()
+            .into_diagnostic()
+            .wrap_err("Could not create a temporary index")?;
+
+        Ok(Self {
+            index: dir.child("index"),
+            _dir: dir,
+            git_dir: git_cd(&["rev-parse", "--absolute-git-dir"])?,
+        })
+    }
+
+    /// Run git with the temporary index in `work_tree`
+    fn git(&self, work_tree: &Path, args: &[&str]) -> Result<String> {
+        let mut a = vec![
+            format!("--git-dir={}", self.git_dir),
+            "--work-tree=.".into(),
+        ];
+        a.extend(args.iter().map(|s| s.to_string()));
+
+        let out = duct::cmd("git", a)
+            .dir(work_tree)
+            .env("GIT_INDEX_FILE", &self.index)
+            .stderr_to_stdout()
+            .unchecked()
+            .stdout_capture()
+            .run()
+            .into_diagnostic()
+            .wrap_err("failed to launch git")?;
+
+        let output = String::from_utf8_lossy(&out.stdout);
+        let output = output.trim();
+
+        if !out.status.success() {
+            Err(miette!("{output}").wrap_err("git command failed"))
+        } else {
+            Ok(output.to_string())
+        }
+    }
+}
+
+/// Commit the content of the store to [STORE_REF], also merging `merged` if given
+///
+/// The outbox, the archive and the configuration are local to each machine and are not mirrored.
+/// Returns the commit the ref points to.
+pub fn commit_store(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    store: &PatchStore,
+    merged: Option<&str>,
+) -> Result<String> {
+    let index = TempIndex::new(&git_cd)?;
+    index.git(
+        store.dir(),
+        &[
+            "add",
+            "--all",
+            "--force",
+            "--",
+            ".",
+            &format!(":(exclude){OUTBOX_NAME}"),
+            &format!(":(exclude){CONFIG_NAME}"),
+            &format!(":(exclude){ARCHIVE_NAME}"),
+            ":(exclude,glob)**/.*",
+        ],
+    )?;
+    let tree = index.git(store.dir(), &["write-tree"])?;
+
+    let current = git_cd(&["rev-parse", "--verify", "--quiet", STORE_REF]).ok();
+
+    let mut parents: Vec<&str> = current.as_deref().into_iter().collect();
+    if let Some(merged) = merged {
+        let known = match &current {
+            Some(current) => git_cd(&["merge-base", "--is-ancestor", merged, current]).is_ok(),
+            None => false,
+        };
+        if !known {
+            parents.push(merged);
+        }
+    }
+
+    if let [parent] = parents.as_slice() {
+        if git_cd(&["rev-parse", &format!("{parent}^{{tree}}")])? == tree {
+            return Ok(parent.to_string());
+        }
+    }
+
+    let mut commit_args = vec!["commit-tree", &tree, "-m", "Update the patch store"];
+    for parent in &parents {
+        commit_args.extend(["-p", parent]);
+    }
+    let commit = git_cd(&commit_args)?;
+
+    git_cd(&[
+        "update-ref",
+        STORE_REF,
+        &commit,
+        current.as_deref().unwrap_or_default(),
+    ])?;
+
+    Ok(commit)
+}
+
+/// Fetch the store of `remote`, returning its commit if it has one
+pub fn fetch(git_cd: impl Fn(&[&str]) -> Result<String>, remote: &str) -> Result<Option<String>> {
+    if git_cd(&["ls-remote", remote, STORE_REF])?.is_empty() {
+        return Ok(None);
+    }
+
+    let tracking = remote_ref(remote);
+    git_cd(&["fetch", remote, &format!("+{STORE_REF}:{tracking}")])?;
+
+    git_cd(&["rev-parse", &tracking]).map(Some)
+}
+
+/// Merge a mirrored store into the local one, returning the number of updated versions
+///
+/// Series removed on either side since their last change are not brought back, and the local
+/// series removed on the other side are moved to the archive.
+pub fn merge(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    store: &PatchStore,
+    commit: &str,
+) -> Result<usize> {
+    let dir = TempDir::new()
+        .into_diagnostic()
+        .wrap_err("Could not create a directory for the mirrored store")?;
+
+    let index = TempIndex::new(&git_cd)?;
+    index.git(dir.path(), &["read-tree", commit])?;
+    index.git(dir.path(), &["checkout-index", "--all", "--force"])?;
+
+    let mirrored = PatchStore::open(dir.path().to_path_buf())?;
+    let tombstones = store.merge_tombstones(mirrored.tombstones()?)?;
+    let tombstone = |name: &str| tombstones.iter().find(|t| t.series == name);
+
+    let mut updated = 0;
+    for series in mirrored.all_series()? {
+        if let Some(tombstone) = tombstone(series.name()) {
+            if SeriesMetadata::load(series.dir())?.is_buried_by(tombstone) {
+                continue;
+            }
+        }
+        updated += merge_series(&store.series(series.name()), &series)?;
+    }
+
+    for series in store.all_series()? {
+        let Some(tombstone) = tombstone(series.name()) else {
+            continue;
+        };
+        if !SeriesMetadata::load(series.dir())?.is_buried_by(tombstone) {
+            continue;
+        }
+
+        let archived = store.archive().series(series.name());
+        match archived.exists() {
+            true => println!(
+                "{} was removed on another machine, but is already in the archive",
+                series.name()
+            ),
+            false => {
+                series.move_to(&archived)?;
+                println!("Archived {}, removed on another machine", series.name());
+            }
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Merge a series per version, the most recently formatted version winning
+fn merge_series(local: &Series, mirrored: &Series) -> Result<usize> {
+    local.create()?;
+
+    let mut metadata = SeriesMetadata::load(local.dir())?;
+    let mirrored_metadata = SeriesMetadata::load(mirrored.dir())?;
+
+    let mut updated = 0;
+    for version in mirrored.versions()? {
+        let local_version = local.version(version.number);
+
+        let formatted = |m: &SeriesMetadata| m.version(version.number).and_then(|v| v.formatted);
+        let newer = match (formatted(&metadata), formatted(&mirrored_metadata)) {
+            (Some(local), Some(mirrored)) => mirrored > local,
+            (None, Some(_)) => true,
+            (_, None) => false,
+        };
+
+        if local_version.exists() && !newer {
+            continue;
+        }
+
+        if local_version.exists() {
+            std::fs::remove_dir_all(local_version.dir())
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not replace {:?}", local_version.dir()))?;
+        }
+        store::copy_dir(version.dir(), local_version.dir())?;
+        updated += 1;
+    }
+
+    let cover_letter = local.cover_letter_path();
+    if !cover_letter.exists() && mirrored.cover_letter_path().exists() {
+        std::fs::copy(mirrored.cover_letter_path(), &cover_letter)
+            .into_diagnostic()
+            .wrap_err("Could not copy the cover letter")?;
+    }
+
+    metadata.merge(mirrored_metadata);
+    metadata.save(local.dir())?;
+
+    Ok(updated)
+}
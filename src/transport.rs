@@ -0,0 +1,551 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use lettre::{
+    address::Envelope, transport::smtp::authentication::Credentials, SmtpTransport, Transport as _,
+};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use temp_dir::TempDir;
+
+use crate::{
+    mail::{address_of, Email},
+    GsmConfig,
+};
+
+/// Outcome of sending an email, reported to the caller of [Transport::send_all]
+pub type SentCallback<'a> = dyn FnMut(&Email, &Result<()>) -> Result<()> + 'a;
+
+/// A way of delivering prepared emails
+pub trait Transport {
+    fn send(&mut self, email: &Email) -> Result<()>;
+
+    /// Deliver `emails` in order, waiting `throttle` between them, and report each of them to
+    /// `sent` until one fails
+    fn send_all(
+        &mut self,
+        emails: &[Email],
+        throttle: Option<Duration>,
+        sent: &mut SentCallback,
+    ) -> Result<()> {
+        for (i, email) in emails.iter().enumerate() {
+            if let (Some(throttle), true) = (throttle, i != 0) {
+                std::thread::sleep(throttle);
+            }
+
+            println!("Sending {}", email.file_name);
+            let result = self.send(email);
+            sent(email, &result)?;
+            result?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransportKind {
+    /// Use `git send-email`, configured through `sendmail_args`
+    #[default]
+    SendEmail,
+    /// Talk to the SMTP server configured in the `smtp` section
+    Smtp,
+    /// Write each email as a separate file in the `sink` directory
+    File,
+    /// Append the emails to the `sink` mbox file
+    Mbox,
+    /// Deliver the emails in the `sink` maildir
+    Maildir,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encryption {
+    /// Implicit TLS, usually on port 465
+    Tls,
+    /// Upgrade the connection with STARTTLS, usually on port 587
+    #[default]
+    Starttls,
+    /// Plain text connection, only meant for local servers
+    None,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SmtpConfig {
+    server: String,
+    port: Option<u16>,
+    #[serde(default)]
+    encryption: Encryption,
+    user: Option<String>,
+    /// When missing the password is asked to the git credential helper
+    password: Option<String>,
+}
+
+pub fn from_config(config: &GsmConfig) -> Result<Box<dyn Transport>> {
+    Ok(match config.transport {
+        TransportKind::SendEmail => Box::new(SendEmail::new(config)),
+        TransportKind::Smtp => {
+            let smtp = config.smtp.as_ref().ok_or(miette!(
+                "The smtp transport requires an `smtp` configuration section"
+            ))?;
+            Box::new(Smtp::connect(smtp)?)
+        }
+        kind @ (TransportKind::File | TransportKind::Mbox | TransportKind::Maildir) => {
+            let path = config
+                .sink
+                .clone()
+                .ok_or(miette!("The {kind:?} transport requires a `sink` path"))?;
+            Box::new(match kind {
+                TransportKind::File => Sink::file(path)?,
+                TransportKind::Mbox => Sink::mbox(path)?,
+                _ => Sink::maildir(path)?,
+            })
+        }
+    })
+}
+
+pub struct SendEmail {
+    args: Vec<String>,
+}
+
+impl SendEmail {
+    pub fn new(config: &GsmConfig) -> Self {
+        Self {
+            args: config.sendmail_args.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Each email is sent by its own `git send-email`, so that an interrupted series records exactly
+/// which emails went out
+impl Transport for SendEmail {
+    fn send(&mut self, email: &Email) -> Result<()> {
+        let dir = TempDir::new()
+            .into_diagnostic()
+            .wrap_err("Could not create temporary patch directory")?;
+        let patch = dir.path().join(&email.file_name);
+        std::fs::write(&patch, email.render_patch())
+            .into_diagnostic()
+            .wrap_err("Could not write patch to send")?;
+
+        // The threading headers are part of the rendered patch, and the series was already
+        // reviewed when running `gsm send`
+        let mut cmd = std::process::Command::new("git");
+        cmd.arg("send-email");
+        cmd.arg("--confirm=never");
+        cmd.args(&self.args);
+        cmd.arg("--no-thread");
+        cmd.arg(&patch);
+
+        let status = cmd
+            .status()
+            .into_diagnostic()
+            .wrap_err("Could not send emails")?;
+
+        if !status.success() {
+            return Err(miette!("Could not send emails"));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Smtp {
+    transport: SmtpTransport,
+}
+
+impl Smtp {
+    pub fn connect(config: &SmtpConfig) -> Result<Self> {
+        let builder = match config.encryption {
+            Encryption::Tls => SmtpTransport::relay(&config.server),
+            Encryption::Starttls => SmtpTransport::starttls_relay(&config.server),
+            Encryption::None => Ok(SmtpTransport::builder_dangerous(&config.server)),
+        }
+        .into_diagnostic()
+        .wrap_err("Could not configure the SMTP transport")?;
+
+        let port = config.port.unwrap_or(match config.encryption {
+            Encryption::Tls => 465,
+            Encryption::Starttls => 587,
+            Encryption::None => 25,
+        });
+        let builder = builder.port(port);
+
+        let credential = match (&config.user, &config.password) {
+            (None, _) => None,
+            (Some(user), Some(password)) => Some((user.clone(), password.clone(), None)),
+            (Some(user), None) => {
+                let request = CredentialRequest {
+                    host: format!("{}:{port}", config.server),
+                    username: user.clone(),
+                };
+                let password = request.fill()?;
+                Some((user.clone(), password, Some(request)))
+            }
+        };
+
+        let builder = match &credential {
+            Some((user, password, _)) => {
+                builder.credentials(Credentials::new(user.clone(), password.clone()))
+            }
+            None => builder,
+        };
+        let transport = builder.build();
+
+        let connected = transport.test_connection();
+        if let Some((_, password, Some(request))) = &credential {
+            let action = match connected {
+                Ok(true) => "approve",
+                _ => "reject",
+            };
+            request.run(action, Some(password))?;
+        }
+
+        match connected {
+            Ok(true) => Ok(Self { transport }),
+            Ok(false) => Err(miette!("Could not connect to {}:{port}", config.server)),
+            Err(e) => Err(e)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not connect to {}:{port}", config.server)),
+        }
+    }
+}
+
+impl Transport for Smtp {
+    fn send(&mut self, email: &Email) -> Result<()> {
+        let sender = address_of(&email.sender)
+            .parse()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid sender address {}", email.sender))?;
+        let recipients = email
+            .recipients()
+            .map(|r| {
+                r.parse()
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Invalid recipient address {r}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let envelope = Envelope::new(Some(sender), recipients)
+            .into_diagnostic()
+            .wrap_err("Email has no recipients")?;
+
+        self.transport
+            .send_raw(&envelope, to_crlf(&email.render()).as_bytes())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not send {}", email.file_name))?;
+
+        Ok(())
+    }
+}
+
+/// Terminate the lines with CRLF as SMTP requires, keeping those already ending so, like the
+/// lines of patches to CRLF files
+fn to_crlf(message: &str) -> String {
+    let mut out = String::with_capacity(message.len() + message.len() / 32);
+    for line in message.split_inclusive('\n') {
+        match line.strip_suffix('\n') {
+            Some(line) => {
+                out += line.strip_suffix('\r').unwrap_or(line);
+                out += "\r\n";
+            }
+            None => out += line,
+        }
+    }
+
+    out
+}
+
+/// Interactions with `git credential` for the SMTP password, like `git send-email` does
+struct CredentialRequest {
+    host: String,
+    username: String,
+}
+
+impl CredentialRequest {
+    fn run(&self, action: &str, password: Option<&str>) -> Result<String> {
+        let mut input = format!(
+            "protocol=smtp\nhost={}\nusername={}\n",
+            self.host, self.username
+        );
+        if let Some(password) = password {
+            input += &format!("password={password}\n");
+        }
+        input.push('\n');
+
+        let out = duct::cmd("git", ["credential", action])
+            .stdin_bytes(input)
+            .stdout_capture()
+            .unchecked()
+            .run()
+            .into_diagnostic()
+            .wrap_err("failed to launch git credential")?;
+
+        if !out.status.success() {
+            return Err(miette!("git credential {action} failed"));
+        }
+
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    fn fill(&self) -> Result<String> {
+        let credential = self.run("fill", None)?;
+
+        credential
+            .lines()
+            .find_map(|l| l.strip_prefix("password="))
+            .map(String::from)
+            .ok_or(miette!("git credential did not provide a password"))
+    }
+}
+
+/// Local destinations for emails, mostly useful to review a series before sending it
+pub enum Sink {
+    File { dir: PathBuf },
+    Mbox { path: PathBuf },
+    Maildir { dir: PathBuf },
+}
+
+impl Sink {
+    pub fn file(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not create sink directory {dir:?}"))?;
+
+        Ok(Self::File { dir })
+    }
+
+    pub fn mbox(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not create mbox directory {parent:?}"))?;
+        }
+
+        Ok(Self::Mbox { path })
+    }
+
+    pub fn maildir(dir: PathBuf) -> Result<Self> {
+        for sub in ["cur", "new", "tmp"] {
+            std::fs::create_dir_all(dir.join(sub))
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not create maildir {dir:?}"))?;
+        }
+
+        Ok(Self::Maildir { dir })
+    }
+}
+
+/// Name of a file unique to this email, following the maildir conventions
+fn unique_name(email: &Email) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let name = Path::new(&email.file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    format!(
+        "{}.M{}P{}.{name}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id()
+    )
+}
+
+impl Transport for Sink {
+    fn send(&mut self, email: &Email) -> Result<()> {
+        let message = email.render();
+
+        match self {
+            Sink::File { dir } => {
+                let path = dir.join(format!("{}.eml", unique_name(email)));
+                std::fs::write(&path, message)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not write {path:?}"))?;
+            }
+            Sink::Mbox { path } => {
+                let date = chrono::DateTime::parse_from_rfc2822(&email.date)
+                    .map(|d| d.format("%a %b %e %T %Y").to_string())
+                    .unwrap_or_else(|_| email.date.clone());
+
+                let mut entry = format!("From {} {date}\n", address_of(&email.sender));
+                for line in message.lines() {
+                    // mboxrd quoting
+                    if line.trim_start_matches('>').starts_with("From ") {
+                        entry.push('>');
+                    }
+                    entry += line;
+                    entry.push('\n');
+                }
+                entry.push('\n');
+
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut f| f.write_all(entry.as_bytes()))
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not append to mbox {path:?}"))?;
+            }
+            Sink::Maildir { dir } => {
+                let name = unique_name(email);
+                let tmp = dir.join("tmp").join(&name);
+                std::fs::write(&tmp, message)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not write {tmp:?}"))?;
+                std::fs::rename(&tmp, dir.join("new").join(&name))
+                    .into_diagnostic()
+                    .wrap_err("Could not deliver email in maildir")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::*;
+    use crate::mail::{prepare_series, Field, Recipient};
+
+    /// Envelope and data of a message received by [smtp_server]
+    struct Received {
+        commands: Vec<String>,
+        data: String,
+    }
+
+    /// Accept SMTP sessions on a local port, passing the received messages to the returned channel
+    fn smtp_server() -> (u16, mpsc::Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut reply = |line: &str| stream.write_all(format!("{line}\r\n").as_bytes());
+                reply("220 localhost").unwrap();
+
+                let mut commands = Vec::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) != 0 {
+                    let command = std::mem::take(&mut line).trim_end().to_string();
+                    let verb = command.split(' ').next().unwrap_or_default().to_uppercase();
+                    match verb.as_str() {
+                        "EHLO" => reply("250-localhost\r\n250 8BITMIME"),
+                        "DATA" => {
+                            reply("354 go ahead").unwrap();
+                            let mut data = String::new();
+                            while reader.read_line(&mut line).unwrap() != 0 {
+                                let data_line = std::mem::take(&mut line);
+                                if data_line == ".\r\n" {
+                                    break;
+                                }
+                                data += &data_line;
+                            }
+                            tx.send(Received {
+                                commands: std::mem::take(&mut commands),
+                                data,
+                            })
+                            .unwrap();
+                            reply("250 queued")
+                        }
+                        "QUIT" => {
+                            reply("221 bye").unwrap();
+                            break;
+                        }
+                        "MAIL" | "RCPT" => {
+                            commands.push(command);
+                            reply("250 ok")
+                        }
+                        _ => reply("250 ok"),
+                    }
+                    .unwrap();
+                }
+            }
+        });
+
+        (port, rx)
+    }
+
+    #[test]
+    fn crlf_only_converts_bare_lf() {
+        assert_eq!(to_crlf("a\nb\r\nc"), "a\r\nb\r\nc");
+        assert_eq!(to_crlf("+dos line\r\n\n"), "+dos line\r\n\r\n");
+        assert_eq!(to_crlf(""), "");
+    }
+
+    #[test]
+    fn smtp_sends_series() {
+        let (port, received) = smtp_server();
+
+        let dir = TempDir::new().unwrap();
+        let patch = dir.path().join("v1-0001-change.patch");
+        std::fs::write(
+            &patch,
+            "From: Author <author@example.com>\n\
+             Subject: [PATCH] Change\n\
+             Cc: reviewer@example.com\n\
+             \n\
+             Message\n\
+             .dotted line\n",
+        )
+        .unwrap();
+        let emails = prepare_series(
+            &[patch],
+            "Sender <sender@example.com>",
+            &[Recipient::new("list@example.com", Field::To, "configured")],
+        )
+        .unwrap();
+
+        let mut smtp = Smtp::connect(&SmtpConfig {
+            server: "127.0.0.1".into(),
+            port: Some(port),
+            encryption: Encryption::None,
+            user: None,
+            password: None,
+        })
+        .unwrap();
+
+        let mut reported = Vec::new();
+        smtp.send_all(&emails, None, &mut |email, result| {
+            reported.push((email.file_name.clone(), result.is_ok()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(reported, vec![("v1-0001-change.patch".to_string(), true)]);
+
+        let message = received
+            .recv_timeout(Duration::from_secs(10))
+            .expect("no message received");
+        assert_eq!(message.commands[0], "MAIL FROM:<sender@example.com>");
+        assert_eq!(
+            message.commands[1..],
+            [
+                "RCPT TO:<list@example.com>",
+                "RCPT TO:<reviewer@example.com>"
+            ]
+        );
+        assert!(message
+            .data
+            .starts_with("From: Sender <sender@example.com>\r\n"));
+        assert!(message
+            .data
+            .contains("\r\n\r\nFrom: Author <author@example.com>\r\n\r\nMessage\r\n"));
+        // Lines starting with a dot are escaped on the wire
+        assert!(message.data.contains("\r\n..dotted line\r\n"));
+    }
+}
@@ -0,0 +1,103 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use miette::Result;
+
+use crate::{mail, patch_id};
+
+/// A patch of a series, as recognised upstream
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// Commit of the change, or the one the patch was generated from
+    pub commit: String,
+    pub patch_id: Option<String>,
+    pub subject: String,
+    /// Email address of the author
+    pub author: String,
+}
+
+/// Changes of the commits in `range`, oldest first
+pub fn of_range(git_cd: impl Fn(&[&str]) -> Result<String>, range: &str) -> Result<Vec<Change>> {
+    let ids = patch_id::of_range(&git_cd, range)?;
+
+    let log = git_cd(&[
+        "log",
+        "--reverse",
+        "--no-merges",
+        "--format=%H%x00%ae%x00%s",
+        range,
+    ])?;
+
+    Ok(log
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.splitn(3, '\0');
+            let commit = fields.next()?;
+            Some(Change {
+                commit: commit.to_string(),
+                patch_id: ids
+                    .iter()
+                    .find(|p| p.commit == commit)
+                    .map(|p| p.id.clone()),
+                author: fields.next()?.to_lowercase(),
+                subject: fields.next()?.trim().to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Changes of stored patches, skipping the cover letter
+pub fn of_patches(patches: &[PathBuf]) -> Result<Vec<Change>> {
+    let ids = patch_id::of_patches(patches)?;
+    let commits = patch_id::patch_commits(patches)?;
+
+    let mut changes = Vec::new();
+    for (patch, commit) in patches
+        .iter()
+        .filter(|p| !p.to_string_lossy().ends_with("cover-letter.patch"))
+        .zip(commits)
+    {
+        let patch = mail::Patch::read(patch)?;
+        changes.push(Change {
+            patch_id: ids
+                .iter()
+                .find(|p| p.commit == commit)
+                .map(|p| p.id.clone()),
+            commit,
+            author: mail::address_of(patch.header("From").unwrap_or_default()).to_lowercase(),
+            subject: patch.subject(),
+        });
+    }
+
+    Ok(changes)
+}
+
+/// The changes applied upstream
+///
+/// Patches are matched by patch-id, or by subject and author as maintainers often touch them up
+/// when applying them.
+pub struct Upstream {
+    patch_ids: HashSet<String>,
+    subjects: HashSet<(String, String)>,
+}
+
+impl Upstream {
+    /// Changes of the commits in `range`, usually `<base>..<upstream>`
+    pub fn load(git_cd: impl Fn(&[&str]) -> Result<String>, range: &str) -> Result<Self> {
+        let changes = of_range(git_cd, range)?;
+
+        Ok(Self {
+            patch_ids: changes.iter().filter_map(|c| c.patch_id.clone()).collect(),
+            subjects: changes.into_iter().map(|c| (c.subject, c.author)).collect(),
+        })
+    }
+
+    pub fn contains(&self, change: &Change) -> bool {
+        change
+            .patch_id
+            .as_ref()
+            .is_some_and(|id| self.patch_ids.contains(id))
+            || self
+                .subjects
+                .contains(&(change.subject.clone(), change.author.clone()))
+    }
+}
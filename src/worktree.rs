@@ -0,0 +1,74 @@
+use std::{ops::Deref, path::PathBuf};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use temp_dir::TempDir;
+
+use crate::git_bare;
+
+/// A temporary detached worktree, removed when dropped
+pub struct Worktree {
+    _dir: TempDir,
+    path: String,
+}
+
+impl Worktree {
+    pub fn new(git_cd: impl Fn(&[&str]) -> Result<String>, commit: &str) -> Result<Self> {
+        let dir = TempDir::new()
+            .into_diagnostic()
+            .wrap_err("Could not create worktree directory")?;
+
+        let path = dir
+            .path()
+            .to_str()
+            .ok_or(miette!("Temp dir is not utf-8"))?
+            .to_string();
+
+        git_cd(&["worktree", "add", "--detach", &path, commit])?;
+
+        Ok(Self { _dir: dir, path })
+    }
+
+    pub fn exec(&self, args: &[&str]) -> Result<String> {
+        let mut a = vec!["-C", &self.path];
+        a.extend_from_slice(args);
+
+        git_bare(a)
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        self.exec(&["worktree", "remove", "--force", &self.path])
+            .unwrap();
+    }
+}
+
+/// Apply stored patches on top of `base`, skipping the cover letter, returning the resulting commit
+pub fn apply_patches(
+    git_cd: impl Fn(&[&str]) -> Result<String>,
+    base: &str,
+    patches: &[PathBuf],
+) -> Result<String> {
+    let wt = Worktree::new(git_cd, base)?;
+
+    let mut patch_paths = Vec::new();
+    for patch in patches {
+        let path = patch
+            .to_str()
+            .ok_or(miette!("Patch path {patch:?} is not utf-8"))?;
+        if !path.ends_with("cover-letter.patch") {
+            patch_paths.push(path.to_string());
+        }
+    }
+
+    if !patch_paths.is_empty() {
+        let mut apply_args = vec!["am", "-3"];
+        apply_args.extend(patch_paths.iter().map(|s| s.deref()));
+        if let Err(e) = wt.exec(&apply_args) {
+            let _ = wt.exec(&["am", "--abort"]);
+            return Err(e.wrap_err(format!("Could not apply the patches on {base}")));
+        }
+    }
+
+    wt.exec(&["rev-parse", "HEAD"])
+}